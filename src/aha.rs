@@ -1,17 +1,77 @@
 use super::github;
+use super::models::{Attachment, Comment, Epic, Feature, Product, Release, Requirement, User};
 use super::Opt;
 use notify_rust::Notification;
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde_json::Value;
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::marker::PhantomData;
 use std::process::Command;
 use termion::clear;
 use termion::cursor;
 use text_io::read;
 use url::Url;
+
+// Everything that can go wrong talking to aha. AhaFS turns these into errno
+// values, the cli just prints them.
+#[derive(Debug)]
+pub enum AhaError {
+    // the request never got a response: dns, tls, timeouts, ...
+    Transport(reqwest::Error),
+    // aha answered with a non-success status
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    // the response (or a request we built) wasn't the json we expected
+    Decode(serde_json::Error),
+    // a 404, or a record missing from an otherwise good response
+    NotFound(String),
+    // running with --offline and the store doesn't have it
+    Offline(String),
+}
+
+impl fmt::Display for AhaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AhaError::Transport(err) => write!(f, "request failed: {}", err),
+            AhaError::Status { status, body } => write!(f, "aha returned {}: {}", status, body),
+            AhaError::Decode(err) => write!(f, "couldn't decode response: {}", err),
+            AhaError::NotFound(what) => write!(f, "not found: {}", what),
+            AhaError::Offline(what) => write!(f, "offline and not stored: {}", what),
+        }
+    }
+}
+
+impl Error for AhaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AhaError::Transport(err) => Some(err),
+            AhaError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for AhaError {
+    fn from(err: reqwest::Error) -> Self {
+        AhaError::Transport(err)
+    }
+}
+
+impl From<serde_json::Error> for AhaError {
+    fn from(err: serde_json::Error) -> Self {
+        AhaError::Decode(err)
+    }
+}
+
 pub struct Aha<'a> {
     pub domain: String,
     pub client: reqwest::Client,
@@ -20,19 +80,7 @@ pub struct Aha<'a> {
 }
 
 impl<'a> Aha<'a> {
-    pub fn get_uri(&self, uri: &str, parent: Option<&String>) -> Vec<Value> {
-        let result: Vec<Value> = vec![];
-
-        let mut count = uri.matches("/").count();
-        dbg!(uri, &parent);
-        match count {
-            1 => self.projects()["products"].as_array().unwrap().to_vec(),
-            2 => self.releases(parent.unwrap().clone()),
-            4 => self.features(parent.unwrap().clone()),
-            _ => result,
-        }
-    }
-    pub fn generate(&self) -> Result<Value, serde_json::Error> {
+    pub fn generate(&self) -> Result<Feature, AhaError> {
         self.reset_screen();
         println!("Enter feature name:");
         let name: String = read!("{}\n");
@@ -44,20 +92,11 @@ impl<'a> Aha<'a> {
         let notes: i8 = read!();
         self.reset_screen();
 
-        let mut feature = self.create_feature(name, notes).unwrap()["feature"].take();
+        let feature = self.create_feature(name, notes)?;
 
-        let feature_url = self
-            .url_builder()
-            .join("features/")
-            .unwrap()
-            .join(feature["id"].as_str().unwrap())
-            .unwrap();
-        let description = feature["feature"]["description"]["body"].take();
-        if !description.is_null() {
-            {
-                let mut file = File::create("/tmp/rust-workflow").unwrap();
-                file.write_all(description.to_string().as_bytes()).unwrap();
-            }
+        if let Some(description) = &feature.description.body {
+            let mut file = File::create("/tmp/rust-workflow").unwrap();
+            file.write_all(description.as_bytes()).unwrap();
         }
         Command::new("nvim")
             .arg("/tmp/rust-workflow")
@@ -76,32 +115,9 @@ impl<'a> Aha<'a> {
                 name: "In development".to_string(),
             }),
         };
-
-        let json_string = serde_json::to_string(&update)?;
-
-        if self.opt.verbose {
-            println!("puting json: {}", json_string);
-        }
-        let response = self
-            .client
-            .put(&feature_url.to_string())
-            .json(&update)
-            .send();
-        let content = response.unwrap().text();
-        let text = &content.unwrap_or("".to_string());
-        if self.opt.verbose {
-            println!("updated {:?}", text);
-        }
-        let feature: Result<Value, _> = serde_json::from_str(&text);
-
-        if let Ok(f) = feature {
-            Ok(f)
-        } else {
-            println!("json failed to parse {:?}", text);
-            let ex: Result<_, serde_json::Error> = Err(feature.unwrap_err());
-            ex
-        }
+        self.update_feature(&feature.id, &update)
     }
+
     pub fn url_builder(&self) -> Url {
         let uri = format!("https://{}.aha.io/api/v1/", self.domain);
         Url::parse(&uri).unwrap()
@@ -120,22 +136,17 @@ impl<'a> Aha<'a> {
         );
         default_labels.insert("Needs PM review".to_string(), "In PM review".to_string());
         default_labels.insert("Ready".to_string(), "Ready to ship".to_string());
-        labels
-            .iter()
-            .map(|label| {
-                let default = default_labels.get(label);
-                let x = match &config_labels {
-                    Some(c) => c.get(label).or_else(|| default),
-                    None => default,
-                };
-                match x {
-                    Some(c) => Some(c.clone()),
-                    None => None,
-                }
-            })
-            .filter(|label| label.is_some())
-            .nth(1)
-            .unwrap_or(None)
+        labels.iter().find_map(|label| {
+            let default = default_labels.get(label);
+            let x = match &config_labels {
+                Some(c) => c.get(label).or_else(|| default),
+                None => default,
+            };
+            match x {
+                Some(c) => Some(c.clone()),
+                None => None,
+            }
+        })
     }
     pub fn new(domain: String, auth_token: String, email: String, opt: &Opt) -> Aha {
         let mut headers = reqwest::header::HeaderMap::new();
@@ -173,20 +184,30 @@ impl<'a> Aha<'a> {
         &self,
         pr: github::PullRequest,
         labels: Option<HashMap<String, String>>,
-    ) -> Result<(), failure::Error> {
-        if let Some((source, key)) = self.type_from_name(&pr.name) {
+    ) -> Result<Option<SyncChange>, failure::Error> {
+        if let Some((source, key)) = Aha::type_from_name(&pr.name) {
             if self.opt.verbose {
                 println!("matched {} {} {}", pr.name, source, key);
             }
 
             match self.get_json(key.clone(), source.to_string()) {
-                Ok(feature) => self.update_aha(key, pr, feature, labels, source).unwrap(),
+                Ok(mut feature) => {
+                    // the api wraps the record, e.g. {"feature": {...}}
+                    let current = feature[source.as_str()].take();
+                    let pr_number = pr.number;
+                    let update = self.update_aha(key.clone(), pr, current, labels, source)?;
+                    return Ok(update.map(|update| SyncChange {
+                        key,
+                        pr_number,
+                        update,
+                    }));
+                }
                 Err(error) => println!("Error {}: {}", source, error),
             }
         } else if self.opt.verbose {
             println!("Did not match {}", pr.name);
         }
-        Ok(())
+        Ok(None)
     }
     pub fn generate_update_function(
         &self,
@@ -249,46 +270,27 @@ impl<'a> Aha<'a> {
         }
     }
 
-    pub fn projects(&self) -> Value {
-        let projects_url = self
-            .url_builder()
-            .join("products?per_page=200&fields=releases")
-            .unwrap();
-        let projects = self.get(projects_url, "products".to_string()).unwrap();
-        projects
-    }
-    pub fn releases(&self, project_id: String) -> Vec<Value> {
-        let releases_url = self
-            .url_builder()
-            .join("products/")
-            .unwrap()
-            .join(&format!("{}/", project_id))
-            .unwrap()
-            .join("releases?per_page=2000&fields=id,name")
-            .unwrap();
-        let releases = self.get(releases_url, "releases".to_string()).unwrap();
-        releases["releases"].as_array().unwrap().to_vec()
+    // Walks a list endpoint a page at a time, decoding the array under
+    // `key`, e.g. the "features" in {"features": [...], "pagination": {...}}.
+    pub fn pages<'s, T: DeserializeOwned>(&'s self, url: Url, key: &str) -> Pages<'s, 'a, T> {
+        Pages {
+            aha: self,
+            url,
+            key: key.to_string(),
+            next_page: 1,
+            total_pages: None,
+            item: PhantomData,
+        }
     }
 
-    pub fn features(&self, project_id: String) -> Vec<Value> {
-        println!("feature load {}", project_id);
-        let releases_url = self
-            .url_builder()
-            .join("releases/")
-            .unwrap()
-            .join(&format!("{}/", project_id))
-            .unwrap()
-            .join("features?per_page=2000&fields=id,name,description")
-            .unwrap();
-        let releases = self.get(releases_url, "features".to_string()).unwrap();
-        releases["features"].as_array().unwrap().to_vec()
+    pub fn list<T: DeserializeOwned>(&self, url: Url, key: &str) -> Result<Vec<T>, AhaError> {
+        all(Box::new(self.pages(url, key)))
     }
-    pub fn create_feature(&self, name: String, notes: i8) -> Result<Value, serde_json::Error> {
-        let projects_url = self.url_builder().join("products?per_page=200").unwrap();
-        let projects = self.get(projects_url, "products".to_string()).unwrap();
-        let projects = projects.as_array().unwrap();
+
+    pub fn create_feature(&self, name: String, notes: i8) -> Result<Feature, AhaError> {
+        let projects = all(self.products())?;
         for (index, project) in projects.iter().enumerate() {
-            println!("{}) {} ({})", index, project["name"], project["id"]);
+            println!("{}) {} ({})", index, project.name, project.id);
         }
         println!("Choose a product:");
         let index: usize = read!();
@@ -298,20 +300,18 @@ impl<'a> Aha<'a> {
             .url_builder()
             .join("products/")
             .unwrap()
-            .join(&format!("{}/", projects[index]["id"].as_str().unwrap()))
+            .join(&format!("{}/", projects[index].id))
             .unwrap()
-            .join("releases?exclude_shipped=true&per_page=200")
+            .join("releases?exclude_shipped=true")
             .unwrap();
-        let releases = self.get(releases_url, "releases".to_string()).unwrap();
-        let releases = releases.as_array().unwrap();
+        let releases: Vec<Release> = self.list(releases_url, "releases")?;
         for (index, release) in releases.iter().enumerate() {
-            println!("{}) {} ({})", index, release["name"], release["id"]);
+            println!("{}) {} ({})", index, release.name, release.id);
         }
         println!("Choose a release:");
         let index: usize = read!();
         self.reset_screen();
 
-        let uri = format!("https://{}.aha.io/api/v1/features", self.domain);
         let notes_required = if notes == 1 {
             Some(CustomNotes {
                 notes: "Required".to_string(),
@@ -322,21 +322,11 @@ impl<'a> Aha<'a> {
 
         let feature = FeatureCreate {
             name,
-            release_id: releases[index]["id"].as_str().unwrap().to_string(),
+            release_id: releases[index].id.clone(),
+            description: None,
             custom_fields: notes_required,
         };
-        let json_string = serde_json::to_string(&feature)?;
-        if self.opt.verbose {
-            println!("creating feature json: {}", json_string);
-        }
-        let response = self.client.post(&uri).json(&feature).send();
-        let content = response.unwrap().text();
-        let text = &content.unwrap_or("".to_string());
-        if self.opt.verbose {
-            println!("created {:?}", text);
-        }
-
-        serde_json::from_str(&text)
+        self.post_feature(&feature)
     }
 
     pub fn update_aha(
@@ -346,7 +336,7 @@ impl<'a> Aha<'a> {
         current: Value,
         labels: Option<HashMap<String, String>>,
         base: String,
-    ) -> Result<(), serde_json::Error> {
+    ) -> Result<Option<FeatureUpdate>, AhaError> {
         let uri = format!("https://{}.aha.io/api/v1/{}s/{}", self.domain, base, key);
         let status = self.status_for_labels(pr.labels.clone(), labels);
         let feature = self.generate_update_function(&current, &pr, status);
@@ -367,33 +357,31 @@ impl<'a> Aha<'a> {
                 .show()
                 .unwrap();
         }
-        if !self.opt.dry_run && json_string.len() > 4 {
-            let response = self.client.put(&uri).json(&feature).send();
-            let content = response.unwrap().text();
-            let text = &content.unwrap_or("".to_string());
-            if self.opt.verbose {
-                println!("updated {} {:?}", base, text);
-            }
-            let feature: Result<Value, _> = serde_json::from_str(&text);
-
-            if let Ok(f) = feature {
-                if f[base].is_null() {
-                    println!("json failed to parse {:?}", text);
-                }
-                Ok(())
-            } else {
-                if self.opt.verbose {
-                    println!("json failed to parse {:?}", text);
-                }
-                let ex: Result<(), serde_json::Error> = Err(feature.unwrap_err());
-                ex
-            }
+        if json_string.len() <= 4 {
+            // nothing to change
+            Ok(None)
+        } else if self.opt.dry_run {
+            Ok(Some(feature))
         } else {
-            Ok(())
+            let updated = self.send(self.client.put(&uri).json(&feature), &base)?;
+            if updated[base.as_str()].is_null() {
+                return Err(AhaError::NotFound(format!("{} {}", base, key)));
+            }
+            Ok(Some(feature))
         }
     }
 
-    pub fn type_from_name(&self, name: &str) -> Option<(String, String)> {
+    fn comments_url(&self, base: &str, id: &str) -> Url {
+        self.url_builder()
+            .join(&format!("{}s/", base))
+            .unwrap()
+            .join(&format!("{}/", id))
+            .unwrap()
+            .join("comments")
+            .unwrap()
+    }
+
+    pub fn type_from_name(name: &str) -> Option<(String, String)> {
         //could return enum
         let req = Regex::new(r"^([A-Z]+-\d+-\d+)").unwrap();
         let fet = Regex::new(r"^([A-Z]{1,}-\d{1,})").unwrap();
@@ -408,26 +396,33 @@ impl<'a> Aha<'a> {
         }
     }
 
-    pub fn get(&self, url: Url, base: String) -> Result<Value, serde_json::Error> {
+    pub fn get(&self, url: Url, base: String) -> Result<Value, AhaError> {
         let uri = url.to_string();
         if self.opt.verbose {
             println!("{} url: {}", base, uri);
         }
-        let response = self.client.get(&uri).send();
-        let content = response.unwrap().text();
+        self.send(self.client.get(&uri), &base)
+    }
+
+    // Sends a request and decodes the json body, turning non-success
+    // statuses into errors instead of handing back aha's error page.
+    fn send(&self, request: reqwest::RequestBuilder, base: &str) -> Result<Value, AhaError> {
+        let mut response = request.send()?;
+        let status = response.status();
+        let text = response.text()?;
         if self.opt.verbose {
-            println!("{} text {:?}", base, content);
+            println!("{} {} {:?}", base, status, text);
         }
-        let feature: Result<Value, _> = serde_json::from_str(&content.unwrap_or("".to_string()));
-        if let Ok(fe) = feature {
-            Ok(fe)
+        if status == reqwest::StatusCode::NOT_FOUND {
+            Err(AhaError::NotFound(format!("{} {}", base, response.url())))
+        } else if !status.is_success() {
+            Err(AhaError::Status { status, body: text })
         } else {
-            let ex: Result<Value, serde_json::Error> = Err(feature.unwrap_err());
-            ex
+            Ok(serde_json::from_str(&text)?)
         }
     }
 
-    pub fn get_json(&self, end_path: String, base: String) -> Result<Value, serde_json::Error> {
+    pub fn get_json(&self, end_path: String, base: String) -> Result<Value, AhaError> {
         let uri = format!("https://{}.aha.io/api/v1/", self.domain);
         let url = Url::parse(&uri).unwrap();
 
@@ -441,17 +436,418 @@ impl<'a> Aha<'a> {
     }
 }
 
+// Records asked for per request; the rest come from following pages.
+const PER_PAGE: u64 = 200;
+
+// Decoded records, one page per item.
+pub type PageIter<'s, T> = Box<dyn Iterator<Item = Result<Vec<T>, AhaError>> + 's>;
+
+// A list endpoint fetched lazily, following the "pagination" block aha sends
+// with every list response until current_page reaches total_pages.
+pub struct Pages<'s, 'a, T> {
+    aha: &'s Aha<'a>,
+    url: Url,
+    key: String,
+    next_page: u64,
+    total_pages: Option<u64>,
+    item: PhantomData<T>,
+}
+
+impl<'s, 'a, T: DeserializeOwned> Iterator for Pages<'s, 'a, T> {
+    type Item = Result<Vec<T>, AhaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(total) = self.total_pages {
+            if self.next_page > total {
+                return None;
+            }
+        }
+        let requested = self.next_page;
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("page", &requested.to_string())
+            .append_pair("per_page", &PER_PAGE.to_string());
+        let mut page = match self.aha.get(url, self.key.clone()) {
+            Ok(page) => page,
+            Err(err) => {
+                // don't keep hammering a failing endpoint
+                self.total_pages = Some(0);
+                return Some(Err(err));
+            }
+        };
+        // endpoints without a pagination block fit on one page
+        let pagination = &page["pagination"];
+        let current = pagination["current_page"].as_u64().unwrap_or(requested);
+        let total = if pagination.is_null() {
+            1
+        } else {
+            pagination["total_pages"].as_u64().unwrap_or(current)
+        };
+        self.total_pages = Some(total);
+        self.next_page = current + 1;
+        // a page skipped to (see nth) that is past the end
+        if requested > total.max(1) {
+            return None;
+        }
+        Some(serde_json::from_value(page[self.key.as_str()].take()).map_err(AhaError::from))
+    }
+
+    // Asks for the later page straight away instead of every page before it.
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.next_page += n as u64;
+        self.next()
+    }
+}
+
+// Drains every page into one list, for callers that need all of it at once.
+pub fn all<T>(pages: PageIter<T>) -> Result<Vec<T>, AhaError> {
+    let mut items = vec![];
+    for page in pages {
+        items.extend(page?);
+    }
+    Ok(items)
+}
+
+// A search of the features list endpoint, parsed from text like
+// "login bug tag:auth assignee:someone@example.com updated_since:2019-03-01".
+// Words that aren't filters are searched for in names and descriptions.
+#[derive(Debug, Default, PartialEq)]
+pub struct FeatureSearch {
+    pub q: Option<String>,
+    pub tag: Option<String>,
+    pub assigned_to_user: Option<String>,
+    pub updated_since: Option<String>,
+}
+
+impl FeatureSearch {
+    pub fn parse(text: &str) -> FeatureSearch {
+        let mut search = FeatureSearch::default();
+        let mut words = vec![];
+        for word in text.split_whitespace() {
+            match word.splitn(2, ':').collect::<Vec<_>>().as_slice() {
+                ["tag", tag] => search.tag = Some(tag.to_string()),
+                ["assignee", email] => search.assigned_to_user = Some(email.to_string()),
+                ["updated_since", since] => search.updated_since = Some(since.to_string()),
+                _ => words.push(word),
+            }
+        }
+        if !words.is_empty() {
+            search.q = Some(words.join(" "));
+        }
+        search
+    }
+
+    fn params(&self) -> Vec<(&str, &String)> {
+        let params = vec![
+            ("q", &self.q),
+            ("tag", &self.tag),
+            ("assigned_to_user", &self.assigned_to_user),
+            ("updated_since", &self.updated_since),
+        ];
+        params
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value.as_ref()?)))
+            .collect()
+    }
+}
+
+// The calls AhaFS makes, so the filesystem can be built against a fake
+// client in tests.
+pub trait Api {
+    fn products(&self) -> PageIter<'_, Product>;
+    fn releases(&self, product_id: &str) -> PageIter<'_, Release>;
+    fn features(&self, release_id: &str) -> PageIter<'_, Feature>;
+    fn epics(&self, release_id: &str) -> PageIter<'_, Epic>;
+    fn epic_features(&self, epic_id: &str) -> PageIter<'_, Feature>;
+    fn requirements(&self, feature_id: &str) -> PageIter<'_, Requirement>;
+    fn users(&self) -> PageIter<'_, User>;
+    fn assigned_features(&self, email: &str) -> PageIter<'_, Feature>;
+    fn search_features(&self, search: &FeatureSearch) -> PageIter<'_, Feature>;
+    fn release(&self, release_id: &str) -> Result<Release, AhaError>;
+    fn feature(&self, feature_id: &str) -> Result<Feature, AhaError>;
+    fn epic(&self, epic_id: &str) -> Result<Epic, AhaError>;
+    fn requirement(&self, requirement_id: &str) -> Result<Requirement, AhaError>;
+    fn update_feature(
+        &self,
+        feature_id: &str,
+        update: &FeatureUpdateCreate,
+    ) -> Result<Feature, AhaError>;
+    fn post_feature(&self, feature: &FeatureCreate) -> Result<Feature, AhaError>;
+    fn comments(&self, base: &str, id: &str) -> PageIter<'_, Comment>;
+    fn post_comment(&self, base: &str, id: &str, body: &str) -> Result<Comment, AhaError>;
+    fn download(&self, url: &str, offset: u64) -> Result<Box<dyn Read>, AhaError>;
+    fn upload_attachment(
+        &self,
+        base: &str,
+        id: &str,
+        file_name: &str,
+        file: File,
+        length: u64,
+    ) -> Result<Attachment, AhaError>;
+}
+
+// Everything description.md shows in its front matter.
+const FEATURE_LIST: &str = "features?fields=id,name,reference_num,description,workflow_status,\
+                            assigned_to_user,tags,score,release,url,created_at,updated_at";
+
+impl<'a> Api for Aha<'a> {
+    fn products(&self) -> PageIter<'_, Product> {
+        let products_url = self.url_builder().join("products").unwrap();
+        Box::new(self.pages(products_url, "products"))
+    }
+
+    fn releases(&self, product_id: &str) -> PageIter<'_, Release> {
+        let releases_url = self
+            .url_builder()
+            .join("products/")
+            .unwrap()
+            .join(&format!("{}/", product_id))
+            .unwrap()
+            .join("releases?fields=id,name,reference_num,release_date,created_at,updated_at")
+            .unwrap();
+        Box::new(self.pages(releases_url, "releases"))
+    }
+
+    fn features(&self, release_id: &str) -> PageIter<'_, Feature> {
+        let features_url = self
+            .url_builder()
+            .join("releases/")
+            .unwrap()
+            .join(&format!("{}/", release_id))
+            .unwrap()
+            .join(FEATURE_LIST)
+            .unwrap();
+        Box::new(self.pages(features_url, "features"))
+    }
+
+    fn epics(&self, release_id: &str) -> PageIter<'_, Epic> {
+        let epics_url = self
+            .url_builder()
+            .join("releases/")
+            .unwrap()
+            .join(&format!("{}/", release_id))
+            .unwrap()
+            .join("epics?fields=id,name,reference_num,description,created_at,updated_at")
+            .unwrap();
+        Box::new(self.pages(epics_url, "epics"))
+    }
+
+    fn epic_features(&self, epic_id: &str) -> PageIter<'_, Feature> {
+        let features_url = self
+            .url_builder()
+            .join("epics/")
+            .unwrap()
+            .join(&format!("{}/", epic_id))
+            .unwrap()
+            .join(FEATURE_LIST)
+            .unwrap();
+        Box::new(self.pages(features_url, "features"))
+    }
+
+    fn requirements(&self, feature_id: &str) -> PageIter<'_, Requirement> {
+        let requirements_url = self
+            .url_builder()
+            .join("features/")
+            .unwrap()
+            .join(&format!("{}/", feature_id))
+            .unwrap()
+            .join("requirements?fields=id,name,reference_num,description,created_at,updated_at")
+            .unwrap();
+        Box::new(self.pages(requirements_url, "requirements"))
+    }
+
+    // Across every product, like the "assigned to me" list in aha.
+    // Everyone in the account, not just those with features assigned.
+    fn users(&self) -> PageIter<'_, User> {
+        let users_url = self.url_builder().join("users").unwrap();
+        Box::new(self.pages(users_url, "users"))
+    }
+
+    fn assigned_features(&self, email: &str) -> PageIter<'_, Feature> {
+        let mut features_url = self.url_builder().join(FEATURE_LIST).unwrap();
+        features_url
+            .query_pairs_mut()
+            .append_pair("assigned_to_user", email);
+        Box::new(self.pages(features_url, "features"))
+    }
+
+    fn search_features(&self, search: &FeatureSearch) -> PageIter<'_, Feature> {
+        let mut features_url = self.url_builder().join(FEATURE_LIST).unwrap();
+        features_url.query_pairs_mut().extend_pairs(search.params());
+        Box::new(self.pages(features_url, "features"))
+    }
+
+    fn release(&self, release_id: &str) -> Result<Release, AhaError> {
+        let mut release = self.get_json(release_id.to_string(), "release".to_string())?;
+        record(&mut release, "release")
+    }
+
+    fn feature(&self, feature_id: &str) -> Result<Feature, AhaError> {
+        let mut feature = self.get_json(feature_id.to_string(), "feature".to_string())?;
+        record(&mut feature, "feature")
+    }
+
+    fn epic(&self, epic_id: &str) -> Result<Epic, AhaError> {
+        let mut epic = self.get_json(epic_id.to_string(), "epic".to_string())?;
+        record(&mut epic, "epic")
+    }
+
+    fn requirement(&self, requirement_id: &str) -> Result<Requirement, AhaError> {
+        let mut requirement =
+            self.get_json(requirement_id.to_string(), "requirement".to_string())?;
+        record(&mut requirement, "requirement")
+    }
+
+    fn update_feature(
+        &self,
+        feature_id: &str,
+        update: &FeatureUpdateCreate,
+    ) -> Result<Feature, AhaError> {
+        let feature_url = self
+            .url_builder()
+            .join("features/")
+            .unwrap()
+            .join(feature_id)
+            .unwrap();
+        let json_string = serde_json::to_string(&update)?;
+
+        if self.opt.verbose {
+            println!("puting json: {}", json_string);
+        }
+        let mut updated = self.send(
+            self.client.put(&feature_url.to_string()).json(&update),
+            "feature",
+        )?;
+        record(&mut updated, "feature")
+    }
+
+    fn post_feature(&self, feature: &FeatureCreate) -> Result<Feature, AhaError> {
+        let uri = format!("https://{}.aha.io/api/v1/features", self.domain);
+        let json_string = serde_json::to_string(&feature)?;
+        if self.opt.verbose {
+            println!("creating feature json: {}", json_string);
+        }
+        let mut created = self.send(self.client.post(&uri).json(&feature), "feature")?;
+        record(&mut created, "feature")
+    }
+
+    // `base` is "feature", "epic" or "requirement", as for get_json.
+    fn comments(&self, base: &str, id: &str) -> PageIter<'_, Comment> {
+        Box::new(self.pages(self.comments_url(base, id), "comments"))
+    }
+
+    fn post_comment(&self, base: &str, id: &str, body: &str) -> Result<Comment, AhaError> {
+        let comment = CommentCreate {
+            comment: CommentBody {
+                body: body.to_string(),
+            },
+        };
+        if self.opt.verbose {
+            println!("posting comment json: {}", serde_json::to_string(&comment)?);
+        }
+        let comments_url = self.comments_url(base, id).to_string();
+        let mut created = self.send(self.client.post(&comments_url).json(&comment), "comment")?;
+        record(&mut created, "comment")
+    }
+
+    // An attachment's body from `offset` on, read from the connection as the
+    // caller goes rather than all at once.
+    fn download(&self, url: &str, offset: u64) -> Result<Box<dyn Read>, AhaError> {
+        let mut request = self.client.get(url).header(reqwest::header::ACCEPT, "*/*");
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+        let mut response = request.send()?;
+        let status = response.status();
+        if self.opt.verbose {
+            println!("download {} {} from {}", url, status, offset);
+        }
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(AhaError::NotFound(format!("attachment {}", url)));
+        } else if !status.is_success() {
+            let body = response.text()?;
+            return Err(AhaError::Status { status, body });
+        }
+        // a server that ignores the range sends it all from the start
+        if offset > 0 && status != reqwest::StatusCode::PARTIAL_CONTENT {
+            let skipped = io::copy(&mut (&mut response).take(offset), &mut io::sink());
+            if let Err(err) = skipped {
+                let body = format!("skipping to {}: {}", offset, err);
+                return Err(AhaError::Status { status, body });
+            }
+        }
+        Ok(Box::new(response))
+    }
+
+    // Sent as multipart form data, streamed from `file` as the request goes
+    // out.
+    fn upload_attachment(
+        &self,
+        base: &str,
+        id: &str,
+        file_name: &str,
+        file: File,
+        length: u64,
+    ) -> Result<Attachment, AhaError> {
+        let attachments_url = self
+            .url_builder()
+            .join(&format!("{}s/", base))
+            .unwrap()
+            .join(&format!("{}/", id))
+            .unwrap()
+            .join("attachments")
+            .unwrap();
+        if self.opt.verbose {
+            println!(
+                "uploading {} ({} bytes) to {}",
+                file_name, length, attachments_url
+            );
+        }
+        let part = reqwest::multipart::Part::reader_with_length(file, length)
+            .file_name(file_name.to_string());
+        let form = reqwest::multipart::Form::new().part("attachment[data]", part);
+        let request = self
+            .client
+            .post(&attachments_url.to_string())
+            .multipart(form);
+        let mut created = self.send(request, "attachment")?;
+        record(&mut created, "attachment")
+    }
+}
+
+// Pulls the record out of aha's wrapper, e.g. the feature in {"feature": {...}}.
+fn record<T: DeserializeOwned>(response: &mut Value, key: &str) -> Result<T, AhaError> {
+    let value = response[key].take();
+    if value.is_null() {
+        return Err(AhaError::NotFound(key.to_string()));
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+// {"comment": {"body": "<p>...</p>"}}
+#[derive(Serialize, Debug)]
+pub struct CommentCreate {
+    pub comment: CommentBody,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CommentBody {
+    pub body: String,
+}
+
 // keep
 #[derive(Serialize, Debug, Deserialize)]
 pub struct FeatureCreate {
-    name: String,
-    release_id: String,
+    pub name: String,
+    pub release_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub custom_fields: Option<CustomNotes>,
 }
 
 // keep
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Serialize, Debug, Deserialize, Default)]
 pub struct FeatureUpdateCreate {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
@@ -473,6 +869,30 @@ pub struct FeatureUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     workflow_status: Option<WorkflowStatusUpdate>,
 }
+impl FeatureUpdate {
+    pub fn summary(&self) -> String {
+        let mut parts = vec![];
+        if let Some(status) = &self.workflow_status {
+            parts.push(format!("status -> {}", status.name));
+        }
+        if let Some(user) = &self.assigned_to_user {
+            parts.push(format!("assign to {}", user));
+        }
+        if let Some(custom) = &self.custom_fields {
+            parts.push(format!("pull request -> {}", custom.github_url));
+        }
+        parts.join(", ")
+    }
+}
+
+// A PR that moved (or under --dryrun would move) a feature or requirement.
+#[derive(Debug)]
+pub struct SyncChange {
+    pub key: String,
+    pub pr_number: i64,
+    pub update: FeatureUpdate,
+}
+
 //keep
 #[derive(Serialize, Debug, Deserialize)]
 pub struct WorkflowStatusUpdate {
@@ -491,3 +911,32 @@ pub struct CustomFieldGithub {
     #[serde(rename = "pull_request")]
     github_url: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    fn labels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn the_first_label_with_a_status_wins() {
+        let opt = Opt::from_iter(&["rust-aha-fs"]);
+        let aha = Aha::new(
+            "example".to_string(),
+            "token".to_string(),
+            "me@example.com".to_string(),
+            &opt,
+        );
+        let ready = labels(&["bug", "Ready", "In development"]);
+        let status = aha.status_for_labels(ready.clone(), None);
+        assert_eq!(status.as_deref(), Some("Ready to ship"));
+        let mut config = HashMap::new();
+        config.insert("bug".to_string(), "Triage".to_string());
+        let status = aha.status_for_labels(ready, Some(config));
+        assert_eq!(status.as_deref(), Some("Triage"));
+        assert_eq!(aha.status_for_labels(labels(&["bug"]), None), None);
+    }
+}
@@ -0,0 +1,90 @@
+// Tracks when AhaFS last fetched each directory listing and file body, so
+// `ls` can be answered from memory while specs are still re-read often
+// enough to not go stale.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    // the product list under /data
+    Products,
+    // a product's releases, and the fixed epics/features folders under them
+    Releases,
+    // the epics or features in a release or epic
+    Listing,
+    // the description of a single feature or epic
+    Body,
+    // the results of a /search query
+    Search,
+}
+
+pub struct Ttls {
+    pub products: Duration,
+    pub releases: Duration,
+    pub listings: Duration,
+    pub bodies: Duration,
+    pub searches: Duration,
+}
+
+impl Default for Ttls {
+    fn default() -> Ttls {
+        Ttls {
+            products: Duration::from_secs(4 * 60 * 60),
+            releases: Duration::from_secs(30 * 60),
+            listings: Duration::from_secs(60),
+            bodies: Duration::from_secs(15),
+            searches: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Ttls {
+    pub fn of(&self, kind: Kind) -> Duration {
+        match kind {
+            Kind::Products => self.products,
+            Kind::Releases => self.releases,
+            Kind::Listing => self.listings,
+            Kind::Body => self.bodies,
+            Kind::Search => self.searches,
+        }
+    }
+}
+
+// Keyed by what was fetched, e.g. "feature/<id>" for a feature body, so a
+// feature listed in two folders is only fetched once.
+pub struct Cache {
+    ttls: Ttls,
+    fetched: HashMap<String, Instant>,
+}
+
+impl Cache {
+    pub fn new(ttls: Ttls) -> Cache {
+        Cache {
+            ttls,
+            fetched: HashMap::new(),
+        }
+    }
+
+    pub fn ttl(&self, kind: Kind) -> Duration {
+        self.ttls.of(kind)
+    }
+
+    pub fn is_fresh(&self, path: &str, kind: Kind) -> bool {
+        match self.fetched.get(path) {
+            Some(at) => at.elapsed() < self.ttls.of(kind),
+            None => false,
+        }
+    }
+
+    pub fn fetched(&mut self, path: &str) {
+        self.fetched.insert(path.to_string(), Instant::now());
+    }
+
+    // Forgets `path` and everything below it, so the next access goes back
+    // to aha.
+    pub fn invalidate(&mut self, path: &str) {
+        let prefix = format!("{}/", path);
+        self.fetched
+            .retain(|key, _| key != path && !key.starts_with(&prefix));
+    }
+}
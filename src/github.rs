@@ -5,6 +5,8 @@ pub struct GithubEnv {
     pub workflow_login: String,
     pub silent: bool,
     pub verbose: bool,
+    // Print the matching pull requests as a table.
+    pub pr_table: bool,
 }
 
 #[derive(Debug)]
@@ -96,9 +98,9 @@ pub fn pr_data(config: &GithubEnv, author: String, open: bool) -> RootInterface
     res.json().expect("Could not find repo")
 }
 
-pub fn prs(config: GithubEnv) -> Result<Vec<PullRequest>, failure::Error> {
-    let response_body = pr_data(&config, config.workflow_login.clone(), true);
-    if config.verbose {
+pub fn prs(config: GithubEnv, open: bool) -> Result<Vec<PullRequest>, failure::Error> {
+    let response_body = pr_data(&config, config.workflow_login.clone(), open);
+    if config.verbose || config.pr_table {
         pr_table(&response_body);
     }
     let response_data = response_body.items;
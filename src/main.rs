@@ -15,31 +15,44 @@ extern crate serde_derive;
 extern crate structopt;
 #[macro_use]
 extern crate prettytable;
-extern crate netfuse;
 extern crate notify_rust;
 extern crate regex;
+use cache::{Cache, Kind, Ttls};
 use fuse::{
     FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
 };
 use libc::ENOENT;
-use netfuse::MountOptions;
-use netfuse::{DirEntry, LibcError, Metadata, NetworkFilesystem};
-use std::collections::HashMap;
+use mount::{
+    CachePolicy, DirEntry, ExtendedAttributes, LibcError, Links, Metadata, MountOptions,
+    NetworkFilesystem, Renames, StableInodes, Streams,
+};
+use names::{Names, Naming};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::error::Error;
 use std::fs;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
+use std::os::unix::fs::FileExt;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 use time::Timespec;
 extern crate libc;
 extern crate time;
 mod aha;
+mod cache;
 mod github;
+mod markdown;
+mod models;
+mod mount;
+mod names;
+mod objects;
+mod store;
 
+use models::{Attachment, Comment, Epic, Feature, Product, Record, Release, Requirement};
+use objects::{comments_key, epic_key, feature_key, meta_key, release_key, Object};
 use serde::Deserialize;
-use serde_json::Value;
 
 #[derive(StructOpt, Debug)]
 pub struct Opt {
@@ -53,17 +66,49 @@ pub struct Opt {
     verbose: bool,
     #[structopt(short = "c", long = "config")]
     config_file: Option<String>,
-    #[structopt(short = "g", long = "generate")]
-    generate: bool,
     #[structopt(short = "p", long = "prs")]
     pr_status: bool,
     #[structopt(long = "closed")]
     closed: bool,
+    /// Serve the mount from the local store only, without touching the network
+    #[structopt(long = "offline")]
+    offline: bool,
+    /// How files are named: ref-and-name (the default), ref-only or name-only
+    #[structopt(long = "naming")]
+    naming: Option<Naming>,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Mount aha at a directory, creating it if needed (the default, at /tmp/ahafs)
+    #[structopt(name = "mount")]
+    Mount {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// FUSE mount options, e.g. -o allow_other -o ro
+        #[structopt(short = "o", long = "options", raw(number_of_values = "1"))]
+        options: Vec<String>,
+    },
+    /// Unmount a directory mounted with `mount`
+    #[structopt(name = "unmount")]
+    Unmount {
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+    },
+    /// Move aha features along with the labels on your pull requests
+    #[structopt(name = "sync-prs", raw(alias = r#""sync""#))]
+    SyncPrs,
+    /// Create a feature interactively
+    #[structopt(name = "generate")]
+    Generate,
 }
 #[derive(Debug, Deserialize)]
 struct Config {
     aha: Option<AhaConfig>,
     global_integer: Option<u64>,
+    naming: Option<Naming>,
     repos: Option<Vec<RepoConfig>>,
 }
 
@@ -88,18 +133,13 @@ struct Env {
     workflow_login: String,
     workflow_email: String,
 }
-use lazy_static::lazy_static;
-lazy_static! {
-    static ref AHACONFIG: (Env, Opt) = load_config().unwrap();
-}
-
-fn load_config() -> Result<(Env, Opt), Box<dyn Error>> {
+fn load_config() -> Result<(Env, Opt, Vec<RepoConfig>), Box<dyn Error>> {
     //copied config
-    let opt = Opt::from_args();
+    let mut opt = Opt::from_args();
     if opt.verbose {
         println!("{:?}", opt);
     }
-    let home_dir = dirs::home_dir().expect("Could not find home path");
+    let home_dir = dirs::home_dir().ok_or("could not find your home directory")?;
 
     let path_name = match &opt.config_file {
         Some(path) => path.clone(),
@@ -110,24 +150,26 @@ fn load_config() -> Result<(Env, Opt), Box<dyn Error>> {
         println!("{:?}", path_name);
     }
     let config_path = fs::canonicalize(&path_name);
-    let config_info: Option<Config> = match config_path {
+    let mut config_info: Option<Config> = match config_path {
         Ok(path) => {
             if opt.verbose {
                 println!("found {:?}", path_name);
             }
             let display = path.display();
             let mut file = match File::open(&path) {
-                Err(why) => panic!("couldn't open {}: {}", display, why.description()),
+                Err(why) => return Err(format!("couldn't open {}: {}", display, why).into()),
                 Ok(file) => file,
             };
 
             // Read the file contents into a string, returns `io::Result<usize>`
             let mut s = String::new();
-            match file.read_to_string(&mut s) {
-                Err(why) => panic!("couldn't read {}: {}", display, why.description()),
-                Ok(_) => (),
+            if let Err(why) = file.read_to_string(&mut s) {
+                return Err(format!("couldn't read {}: {}", display, why).into());
+            }
+            match toml::from_str(&s) {
+                Ok(config) => Some(config),
+                Err(why) => return Err(format!("couldn't parse {}: {}", display, why).into()),
             }
-            Some(toml::from_str(&s)?)
         }
         Err(e) => {
             if !opt.silent {
@@ -139,11 +181,23 @@ fn load_config() -> Result<(Env, Opt), Box<dyn Error>> {
 
     //dotenv::dotenv().ok();
     let my_path = format!("{}/.env", home_dir.display());
-    dotenv::from_path(my_path).ok();
+    dotenv::from_path(&my_path).ok();
     env_logger::init();
 
-    let mut config: Env = envy::from_env()?;
+    let mut config: Env = match envy::from_env() {
+        Ok(config) => config,
+        Err(why) => {
+            return Err(format!("{} (set it in the environment or {})", why, my_path).into())
+        }
+    };
 
+    if opt.naming.is_none() {
+        opt.naming = config_info.as_ref().and_then(|c| c.naming);
+    }
+    let repos = config_info
+        .as_mut()
+        .and_then(|c| c.repos.take())
+        .unwrap_or_default();
     match config_info.as_ref() {
         Some(c) => match c.aha.as_ref() {
             Some(a) => {
@@ -159,192 +213,1760 @@ fn load_config() -> Result<(Env, Opt), Box<dyn Error>> {
         println!("config updated");
     }
 
-    Ok((config, opt))
+    Ok((config, opt, repos))
 }
-struct AhaFS {
+struct AhaFS<A: aha::Api> {
+    aha: A,
+    naming: Naming,
     products: HashMap<String, String>,
+    product_values: HashMap<String, Product>,
     releases: HashMap<String, String>,
+    release_values: HashMap<String, Release>,
     features: HashMap<String, String>,
-    feature_values: HashMap<String, Value>,
+    feature_values: HashMap<String, Feature>,
     epics: HashMap<String, String>,
+    epic_values: HashMap<String, Epic>,
+    requirements: HashMap<String, String>,
+    requirement_values: HashMap<String, Requirement>,
+    attachments: HashMap<String, String>,
+    attachment_values: HashMap<String, Attachment>,
+    // Attachments being read and copied in, by path.
+    downloads: HashMap<String, Download>,
+    uploads: HashMap<String, Upload>,
+    uploads_started: u64,
+    // The configured user, whose features /mine lists.
+    user_email: String,
+    // The last fetch of each comments.md, and the thread each one was read
+    // as when opened, to tell what was appended.
+    comment_threads: HashMap<String, (String, Times)>,
+    comment_files: HashMap<String, String>,
+    // The last fetch of each meta.json.
+    meta_files: HashMap<String, (String, Times)>,
+    // The names handed out so far by each directory being read, by handle.
+    listings: HashMap<u64, Names>,
+    // Links in the virtual views, by path.
+    links: HashMap<String, Link>,
+    // Empty status directories made with mkdir, to move features into.
+    status_dirs: HashSet<String>,
+    // What each inode handed to the driver stands for.
+    objects: HashMap<u64, Object>,
+    cache: Cache,
 }
-impl AhaFS {
-    pub fn mount(options: MountOptions) {
-        let afs = AhaFS {
+impl<A: aha::Api> AhaFS<A> {
+    pub fn new(aha: A, naming: Naming, user_email: String) -> AhaFS<A> {
+        AhaFS {
+            aha,
+            naming,
             products: HashMap::new(),
+            product_values: HashMap::new(),
             releases: HashMap::new(),
+            release_values: HashMap::new(),
             features: HashMap::new(),
             feature_values: HashMap::new(),
             epics: HashMap::new(),
-        };
-        netfuse::mount(afs, options);
-    }
-}
-
-fn build_dir_entry(item: &Value, path_string: &str) -> DirEntry {
-    dbg!(item, path_string);
-    if path_string.ends_with("/features") {
-        let meta = Metadata {
-            size: item["description"]["body"]
-                .as_str()
-                .unwrap()
-                .to_string()
-                .as_bytes()
-                .len() as u64,
-            atime: DEFAULT_TIME,
-            mtime: DEFAULT_TIME,
-            ctime: DEFAULT_TIME,
-            crtime: DEFAULT_TIME,
-            kind: FileType::RegularFile,
-            perm: 0o640,
-        };
-        DirEntry::new(item["name"].as_str().expect("file has no name"), meta)
-    } else {
-        let meta = Metadata {
-            size: 0,
-            atime: DEFAULT_TIME,
-            mtime: DEFAULT_TIME,
-            ctime: DEFAULT_TIME,
-            crtime: DEFAULT_TIME,
-            kind: FileType::Directory,
-            // TODO: API should indicate if dir is listable or not
-            perm: 0o750,
-        };
-        DirEntry::new(item["name"].as_str().expect("dir has no name"), meta)
+            epic_values: HashMap::new(),
+            requirements: HashMap::new(),
+            requirement_values: HashMap::new(),
+            attachments: HashMap::new(),
+            attachment_values: HashMap::new(),
+            downloads: HashMap::new(),
+            uploads: HashMap::new(),
+            uploads_started: 0,
+            user_email,
+            comment_threads: HashMap::new(),
+            comment_files: HashMap::new(),
+            meta_files: HashMap::new(),
+            listings: HashMap::new(),
+            links: HashMap::new(),
+            status_dirs: HashSet::new(),
+            objects: HashMap::new(),
+            cache: Cache::new(Ttls::default()),
+        }
+    }
+
+    pub fn mount(self, options: &MountOptions) -> std::io::Result<()> {
+        mount::mount(self, options)
     }
 }
 
-fn basic_dir_entry(path: &str, perm: u16) -> DirEntry {
-    let meta = Metadata {
-        size: 0,
-        atime: DEFAULT_TIME,
-        mtime: DEFAULT_TIME,
-        ctime: DEFAULT_TIME,
-        crtime: DEFAULT_TIME,
-        kind: FileType::Directory,
-        perm,
+// When an entry was created and last changed in aha.
+#[derive(Clone, Copy)]
+struct Times {
+    created: Timespec,
+    updated: Timespec,
+}
+
+const UNKNOWN_TIMES: Times = Times {
+    created: DEFAULT_TIME,
+    updated: DEFAULT_TIME,
+};
+
+impl Times {
+    fn of<R: Record>(record: &R) -> Times {
+        let created = parse_time(record.created_at()).unwrap_or(DEFAULT_TIME);
+        Times {
+            created,
+            updated: parse_time(record.updated_at()).unwrap_or(created),
+        }
+    }
+
+    // Releases are dated by when they ship, so `ls -lt` lists them in that
+    // order.
+    fn of_release(release: &Release) -> Times {
+        let times = Times::of(release);
+        let release_date = release.release_date.as_deref();
+        Times {
+            updated: parse_time(release_date).unwrap_or(times.updated),
+            ..times
+        }
+    }
+}
+
+// Aha timestamps look like "2019-03-14T21:09:31.483Z", always in UTC, and
+// release dates like "2019-03-14".
+fn parse_time(value: Option<&str>) -> Option<Timespec> {
+    let value = value?;
+    let tm = match value.get(..19) {
+        Some(datetime) => time::strptime(datetime, "%Y-%m-%dT%H:%M:%S"),
+        None => time::strptime(value, "%Y-%m-%d"),
     };
-    DirEntry::new(path, meta)
+    tm.ok().map(|tm| tm.to_timespec())
+}
+
+fn metadata(kind: FileType, perm: u16, size: u64, times: Times) -> Metadata {
+    Metadata {
+        size,
+        atime: times.updated,
+        mtime: times.updated,
+        ctime: times.created,
+        crtime: times.created,
+        kind,
+        perm,
+    }
+}
+
+fn link_entry(name: &str, target: &str, times: Times) -> DirEntry {
+    let size = target.len() as u64;
+    DirEntry::new(name, metadata(FileType::Symlink, 0o777, size, times))
+}
+
+// A file holding the description body of a feature or epic.
+fn body_entry(name: &str, body: &str, times: Times) -> DirEntry {
+    let size = body.len() as u64;
+    DirEntry::new(name, metadata(FileType::RegularFile, 0o640, size, times))
+}
+
+// Epics are listed twice: as a directory of their features and as a
+// "<name>.html" file with the epic description.
+const EPIC_FILE_SUFFIX: &str = ".html";
+
+// The directory path of the epic an "<epic>.html" file describes.
+fn epic_dir_of(path_string: &str) -> &str {
+    path_string
+        .strip_suffix(EPIC_FILE_SUFFIX)
+        .unwrap_or(path_string)
+}
+
+// Every feature is a directory holding its description and requirements.
+const DESCRIPTION_FILE: &str = "description.md";
+const REQUIREMENTS_DIR: &str = "requirements";
+
+// Attachments are never held in memory whole: a read continues the download
+// the last one left off, and a file copied in is spooled to a temporary file
+// and uploaded from there once it is closed.
+const ATTACHMENTS_DIR: &str = "attachments";
+
+struct Download {
+    offset: u64,
+    body: Box<dyn Read>,
+}
+
+struct Upload {
+    file: File,
+    path: PathBuf,
+}
+
+// Release, epic and feature directories also hold the whole aha record as
+// json, for scripts.
+const META_FILE: &str = "meta.json";
+
+// Epic and feature directories show their comment thread as Markdown, which
+// can only be appended to.
+const COMMENTS_FILE: &str = "comments.md";
+
+// Products and releases also list their features by workflow status, as
+// links to where they are under features/.
+const BY_STATUS_DIR: &str = "by-status";
+
+// Next to /data, links to the configured user's features and to everyone
+// else's, one directory per email.
+const MINE_DIR: &str = "mine";
+const BY_ASSIGNEE_DIR: &str = "by-assignee";
+
+// Any directory name under /search is run as a feature search, see
+// aha::FeatureSearch.
+const SEARCH_DIR: &str = "search";
+
+// Any reference number under /ref, like "ENG-123" or "ENG-123-1", links to
+// where that feature or requirement is. "ENG-123.md" links to the feature's
+// description.md.
+const REF_DIR: &str = "ref";
+const REF_DESCRIPTION_SUFFIX: &str = ".md";
+
+// A feature (or, in /ref, a requirement) as it shows up in a virtual view.
+struct Link {
+    id: String,
+    // where it is under features/
+    target: String,
+}
+
+// Where a path sits in the mount:
+//   /mine/<feature link>
+//   /by-assignee/<email>/<feature link>
+//   /search/<query>/<feature link>
+//   /ref/<reference link>
+//   /data/<product>/by-status/<status>/<feature link>
+//   /data/<product>/<release>/by-status/<status>/<feature link>
+//   /data/<product>/<release>/epics/<epic>.html
+//   /data/<product>/<release>/epics/<epic>/<feature>/...
+//   /data/<product>/<release>/features/<feature>/...
+// with each <feature> holding description.md, requirements/<requirement> and
+// attachments/<file>,
+// a meta.json in every release, epic and feature directory and a comments.md
+// in every epic and feature directory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Node {
+    Root,
+    Mine,
+    Assignees,
+    Assignee,
+    AssigneeLink,
+    Searches,
+    Search,
+    SearchLink,
+    Refs,
+    Ref,
+    Data,
+    Product,
+    Release,
+    Epics,
+    Features,
+    Epic,
+    EpicFile,
+    Feature,
+    Description,
+    Requirements,
+    Requirement,
+    Attachments,
+    Attachment,
+    Meta,
+    Comments,
+    StatusView,
+    Status,
+    StatusLink,
+    Unknown,
+}
+
+fn node_of(path_string: &str) -> Node {
+    let parts = path_string.split('/').skip(1).collect::<Vec<_>>();
+    match parts.as_slice() {
+        [""] => Node::Root,
+        [MINE_DIR] => Node::Mine,
+        [BY_ASSIGNEE_DIR] => Node::Assignees,
+        [BY_ASSIGNEE_DIR, _] => Node::Assignee,
+        [MINE_DIR, _] | [BY_ASSIGNEE_DIR, _, _] => Node::AssigneeLink,
+        [SEARCH_DIR] => Node::Searches,
+        [SEARCH_DIR, _] => Node::Search,
+        [SEARCH_DIR, _, _] => Node::SearchLink,
+        [REF_DIR] => Node::Refs,
+        [REF_DIR, _] => Node::Ref,
+        ["data"] => Node::Data,
+        ["data", _] => Node::Product,
+        ["data", _, BY_STATUS_DIR] | ["data", _, _, BY_STATUS_DIR] => Node::StatusView,
+        ["data", _, BY_STATUS_DIR, _] | ["data", _, _, BY_STATUS_DIR, _] => Node::Status,
+        ["data", _, BY_STATUS_DIR, _, _] | ["data", _, _, BY_STATUS_DIR, _, _] => Node::StatusLink,
+        ["data", _, _] => Node::Release,
+        ["data", _, _, META_FILE] | ["data", _, _, "epics", _, META_FILE] => Node::Meta,
+        ["data", _, _, "epics", _, COMMENTS_FILE] => Node::Comments,
+        ["data", _, _, "epics"] => Node::Epics,
+        ["data", _, _, "features"] => Node::Features,
+        ["data", _, _, "epics", epic] if epic.ends_with(EPIC_FILE_SUFFIX) => Node::EpicFile,
+        ["data", _, _, "epics", _] => Node::Epic,
+        ["data", _, _, "epics", _, feature @ ..] | ["data", _, _, "features", feature @ ..] => {
+            match feature {
+                [_] => Node::Feature,
+                [_, DESCRIPTION_FILE] => Node::Description,
+                [_, META_FILE] => Node::Meta,
+                [_, COMMENTS_FILE] => Node::Comments,
+                [_, REQUIREMENTS_DIR] => Node::Requirements,
+                [_, REQUIREMENTS_DIR, _] => Node::Requirement,
+                [_, ATTACHMENTS_DIR] => Node::Attachments,
+                [_, ATTACHMENTS_DIR, _] => Node::Attachment,
+                _ => Node::Unknown,
+            }
+        }
+        _ => Node::Unknown,
+    }
 }
 
+// The feature directory a description, requirements or attachments folder,
+// requirement or attachment is in.
+fn feature_dir_of(path_string: &str) -> String {
+    let (_, parent_dir) = split_parent(path_string);
+    match node_of(path_string) {
+        Node::Requirement | Node::Attachment => split_parent(&parent_dir).1,
+        _ => parent_dir,
+    }
+}
+
+// The product or release a by-status path is in.
+fn view_scope(path_string: &str) -> String {
+    let parts = path_string.split('/').collect::<Vec<_>>();
+    let view = parts
+        .iter()
+        .skip(3)
+        .position(|part| *part == BY_STATUS_DIR)
+        .unwrap_or(0);
+    parts[..3 + view].join("/")
+}
+
+// `to` relative to the directory of the link at `from`, so links keep
+// working wherever the filesystem is mounted.
+fn relative_path(from: &str, to: &str) -> String {
+    let from_dir = split_parent(from).1;
+    let from_parts = from_dir
+        .split('/')
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>();
+    let to_parts = to.split('/').filter(|p| !p.is_empty()).collect::<Vec<_>>();
+    let common = from_parts
+        .iter()
+        .zip(&to_parts)
+        .take_while(|(from, to)| from == to)
+        .count();
+    let mut parts = vec![".."; from_parts.len() - common];
+    parts.extend(&to_parts[common..]);
+    parts.join("/")
+}
+
+// What kind of thing a path is, for how long it may be cached.
+fn cache_kind(path_string: &str) -> Kind {
+    match node_of(path_string) {
+        Node::Root | Node::Data => Kind::Products,
+        Node::Product | Node::Release => Kind::Releases,
+        Node::Epics
+        | Node::Features
+        | Node::Epic
+        | Node::Feature
+        | Node::Requirements
+        | Node::Attachments
+        | Node::StatusView
+        | Node::Status
+        | Node::StatusLink
+        | Node::Mine
+        | Node::Assignees
+        | Node::Assignee
+        | Node::AssigneeLink
+        | Node::Searches
+        | Node::Refs
+        | Node::Ref => Kind::Listing,
+        Node::Search | Node::SearchLink => Kind::Search,
+        _ => Kind::Body,
+    }
+}
+
+// Keeps a page of features and hands back their ids, in the order aha
+// found them.
+fn keep_features(values: &mut HashMap<String, Feature>, features: Vec<Feature>) -> Vec<String> {
+    let mut found = vec![];
+    for feature in features {
+        found.push(feature.id.clone());
+        values.insert(feature.id.clone(), feature);
+    }
+    found
+}
+
+// Page `page` of a listing, or None past its end. A listing with no pages at
+// all still has an empty first one.
+fn page_of<T>(mut pages: aha::PageIter<T>, page: usize) -> Result<Option<Vec<T>>, LibcError> {
+    match pages.nth(page) {
+        Some(items) => items.map(Some).map_err(api_error),
+        None if page == 0 => Ok(Some(vec![])),
+        None => Ok(None),
+    }
+}
+
+// Splits "/a/b/c" into ("c", "/a/b").
+fn split_parent(path_string: &str) -> (String, String) {
+    let mut parts = path_string.rsplitn(2, "/");
+    let name = parts.next().unwrap_or("").to_string();
+    let parent = parts.next().unwrap_or("").to_string();
+    (name, parent)
+}
+
+// A directory's size is the bytes of the description files directly in it.
+fn basic_dir_entry(path: &str, perm: u16, size: u64, times: Times) -> DirEntry {
+    DirEntry::new(path, metadata(FileType::Directory, perm, size, times))
+}
+
+// For entries aha has no dates for, like /data.
 // 2015-03-12 00:00 PST Algorithmia Launch
 pub const DEFAULT_TIME: Timespec = Timespec {
     sec: 1426147200,
     nsec: 0,
 };
 
-macro_rules! eio {
-    ($fmt:expr) => {{
-        println!($fmt);
-        Err(libc::EIO)
-    }};
-    ($fmt:expr, $($arg:tt)*) => {{
-        println!($fmt, $($arg)*);
-        Err(libc::EIO)
-    }};
+// Logs a failed api call and picks the errno the kernel hands back to
+// whoever touched the file.
+fn api_error(err: aha::AhaError) -> LibcError {
+    println!("AFS api error: {}", err);
+    match &err {
+        aha::AhaError::NotFound(_) => libc::ENOENT,
+        aha::AhaError::Status { status, .. } => match status.as_u16() {
+            401 | 403 => libc::EACCES,
+            400 | 422 => libc::EINVAL,
+            429 | 502 | 503 | 504 => libc::EAGAIN,
+            _ => libc::EIO,
+        },
+        aha::AhaError::Transport(err) if err.is_timeout() => libc::EAGAIN,
+        aha::AhaError::Offline(_) => libc::ENETUNREACH,
+        aha::AhaError::Transport(_) | aha::AhaError::Decode(_) => libc::EIO,
+    }
 }
 
-impl NetworkFilesystem for AhaFS {
-    fn readdir(&mut self, path: &Path) -> Box<dyn Iterator<Item = Result<DirEntry, LibcError>>> {
-        let uri = match path_to_uri(&path) {
-            Ok(u) => u,
-            Err(_) => {
-                // The default root listing
-                return Box::new(vec![Ok(basic_dir_entry("/data", 0o550))].into_iter());
+impl<A: aha::Api> AhaFS<A> {
+    // Lists the children of a directory from the API and remembers their
+    // ids so later lookups and reads can find them without another listing.
+    fn load_dir(&mut self, path_string: &str) -> Result<Vec<DirEntry>, LibcError> {
+        let mut names = self.names_for(path_string);
+        let mut entries = vec![];
+        let mut page = 0;
+        while let Some(listed) = self.load_page(path_string, page, &mut names)? {
+            entries.extend(listed);
+            page += 1;
+        }
+        Ok(entries)
+    }
+
+    // The names a listing of the directory hands out, kept across its pages
+    // so a later page doesn't reuse one.
+    fn names_for(&self, path_string: &str) -> Names {
+        let reserved: &[&str] = match node_of(path_string) {
+            Node::Product => &[BY_STATUS_DIR],
+            Node::Epic => &[META_FILE, COMMENTS_FILE],
+            _ => &[],
+        };
+        Names::new(self.naming, reserved)
+    }
+
+    // Page `page` (from 0) of a directory's listing, or None past the last.
+    // Listings aha pages are handed on a page of theirs at a time; the rest,
+    // and entries of our own like by-status/ and meta.json, come on page 0.
+    fn load_page(
+        &mut self,
+        path_string: &str,
+        page: usize,
+        names: &mut Names,
+    ) -> Result<Option<Vec<DirEntry>>, LibcError> {
+        let (_, parent_dir) = split_parent(path_string);
+        let key = |name: &str| format!("{}/{}", path_string, name);
+        let mut entries = vec![];
+        if page == 0 {
+            self.forget_children(path_string);
+        }
+        match node_of(path_string) {
+            Node::Data => {
+                let products = match page_of(self.aha.products(), page)? {
+                    Some(products) => products,
+                    None => return Ok(None),
+                };
+                for product in products {
+                    let times = Times::of(&product);
+                    let name = names.name(None, &product.name);
+                    entries.push(basic_dir_entry(&name, 0o750, 0, times));
+                    self.products.insert(key(&name), product.id.clone());
+                    self.product_values.insert(product.id.clone(), product);
+                }
+            }
+
+            Node::Product => {
+                let product_id = self.products.get(path_string).ok_or(ENOENT)?;
+                let releases = match page_of(self.aha.releases(product_id), page)? {
+                    Some(releases) => releases,
+                    None => return Ok(None),
+                };
+                for release in releases {
+                    let times = Times::of_release(&release);
+                    let reference = release.reference_num.as_deref();
+                    let name = names.name(reference, &release.name);
+                    entries.push(basic_dir_entry(&name, 0o750, 0, times));
+                    self.releases.insert(key(&name), release.id.clone());
+                    self.release_values.insert(release.id.clone(), release);
+                }
+                if page == 0 {
+                    let times = self.dir_times(path_string);
+                    entries.push(basic_dir_entry(BY_STATUS_DIR, 0o750, 0, times));
+                }
             }
+
+            Node::Epics => {
+                let release_id = self.releases.get(&parent_dir).ok_or(ENOENT)?;
+                let epics = match page_of(self.aha.epics(release_id), page)? {
+                    Some(epics) => epics,
+                    None => return Ok(None),
+                };
+                for epic in epics {
+                    let times = Times::of(&epic);
+                    let reference = epic.reference_num.as_deref();
+                    let name = names.name_with(reference, &epic.name, EPIC_FILE_SUFFIX);
+                    let file_name = format!("{}{}", name, EPIC_FILE_SUFFIX);
+                    entries.push(body_entry(&file_name, epic.description.body(), times));
+                    let size = self.dir_size(&key(&name));
+                    entries.push(basic_dir_entry(&name, 0o750, size, times));
+                    self.cache.fetched(&epic_key(&epic.id));
+                    self.epics.insert(key(&name), epic.id.clone());
+                    self.epic_values.insert(epic.id.clone(), epic);
+                }
+            }
+
+            node @ Node::Features | node @ Node::Epic => {
+                let pages = if node == Node::Features {
+                    let release_id = self.releases.get(&parent_dir).ok_or(ENOENT)?;
+                    self.aha.features(release_id)
+                } else {
+                    let epic_id = self.epics.get(path_string).ok_or(ENOENT)?;
+                    self.aha.epic_features(epic_id)
+                };
+                let features = match page_of(pages, page)? {
+                    Some(features) => features,
+                    None => return Ok(None),
+                };
+                for feature in features {
+                    let times = Times::of(&feature);
+                    let reference = feature.reference_num.as_deref();
+                    let name = names.name(reference, &feature.name);
+                    let size = markdown::feature(&feature).len() as u64;
+                    entries.push(basic_dir_entry(&name, 0o750, size, times));
+                    self.cache.fetched(&feature_key(&feature.id));
+                    self.features.insert(key(&name), feature.id.clone());
+                    self.feature_values.insert(feature.id.clone(), feature);
+                }
+            }
+
+            Node::Requirements => {
+                let feature_id = self.features.get(&parent_dir).ok_or(ENOENT)?;
+                let requirements = match page_of(self.aha.requirements(feature_id), page)? {
+                    Some(requirements) => requirements,
+                    None => return Ok(None),
+                };
+                for requirement in requirements {
+                    let times = Times::of(&requirement);
+                    let reference = requirement.reference_num.as_deref();
+                    let name = names.name(reference, &requirement.name);
+                    let body = requirement.description.body();
+                    entries.push(body_entry(&name, body, times));
+                    let id = requirement.id.clone();
+                    self.requirements.insert(key(&name), id.clone());
+                    self.requirement_values.insert(id, requirement);
+                }
+            }
+
+            Node::Assignees => {
+                let users = match page_of(self.aha.users(), page)? {
+                    Some(users) => users,
+                    None => return Ok(None),
+                };
+                // our own address comes first, whether aha lists it or not
+                let mut emails = BTreeSet::new();
+                if page == 0 {
+                    emails.insert(names::escape(&self.user_email));
+                }
+                let others = users.iter().filter(|user| user.email != self.user_email);
+                emails.extend(others.map(|user| names::escape(&user.email)));
+                for email in emails {
+                    entries.push(basic_dir_entry(&email, 0o750, 0, UNKNOWN_TIMES));
+                }
+            }
+
+            Node::Mine | Node::Assignee => {
+                let email = self.assignee_of(path_string);
+                let features = match page_of(self.aha.assigned_features(&email), page)? {
+                    Some(features) => features,
+                    None => return Ok(None),
+                };
+                let found = keep_features(&mut self.feature_values, features);
+                entries.extend(self.found_links(path_string, found, names));
+            }
+
+            Node::Search => {
+                let query = names::unescape(&split_parent(path_string).0);
+                let search = aha::FeatureSearch::parse(&query);
+                let features = match page_of(self.aha.search_features(&search), page)? {
+                    Some(features) => features,
+                    None => return Ok(None),
+                };
+                let found = keep_features(&mut self.feature_values, features);
+                entries.extend(self.found_links(path_string, found, names));
+            }
+
+            // the rest are listed in one go
+            _ if page > 0 => return Ok(None),
+
+            Node::Release => {
+                let times = self.dir_times(path_string);
+                for folder in &["epics", "features", BY_STATUS_DIR] {
+                    let size = self.dir_size(&key(folder));
+                    entries.push(basic_dir_entry(folder, 0o750, size, times));
+                }
+            }
+
+            Node::Feature => {
+                let feature_id = self.features.get(path_string).ok_or(ENOENT)?;
+                let feature = self.feature_values.get(feature_id).ok_or(ENOENT)?;
+                let times = Times::of(feature);
+                let description = markdown::feature(feature);
+                entries.push(body_entry(DESCRIPTION_FILE, &description, times));
+                let size = self.dir_size(&key(REQUIREMENTS_DIR));
+                entries.push(basic_dir_entry(REQUIREMENTS_DIR, 0o550, size, times));
+                entries.push(basic_dir_entry(ATTACHMENTS_DIR, 0o750, 0, times));
+            }
+
+            Node::Attachments => {
+                let feature = self.feature_file(&parent_dir).ok_or(ENOENT)?;
+                let attachments = feature.description.attachments.clone();
+                for attachment in attachments {
+                    let name = names.name(None, &attachment.file_name);
+                    let size = attachment.file_size.unwrap_or(0);
+                    let times = Times::of(&attachment);
+                    let meta = metadata(FileType::RegularFile, 0o440, size, times);
+                    entries.push(DirEntry::new(&name, meta));
+                    self.attachments.insert(key(&name), attachment.id.clone());
+                    self.attachment_values
+                        .insert(attachment.id.clone(), attachment);
+                }
+            }
+
+            Node::StatusView => {
+                let scope = view_scope(path_string);
+                let times = self.dir_times(&scope);
+                let mut statuses = BTreeSet::new();
+                for (_, feature_id) in self.scope_features(&scope)? {
+                    let feature = self.feature_values.get(&feature_id);
+                    if let Some(status) = feature.and_then(|f| f.workflow_status.as_ref()) {
+                        statuses.insert(names::escape(&status.name));
+                    }
+                }
+                let made = self
+                    .status_dirs
+                    .iter()
+                    .filter(|dir| split_parent(dir).1 == path_string);
+                statuses.extend(made.map(|dir| split_parent(dir).0));
+                for status in statuses {
+                    entries.push(basic_dir_entry(&status, 0o750, 0, times));
+                }
+            }
+
+            Node::Status => {
+                let scope = view_scope(path_string);
+                let status = names::unescape(&split_parent(path_string).0);
+                let mut features = self.scope_features(&scope)?;
+                features.retain(|(_, id)| {
+                    let feature = self.feature_values.get(id);
+                    feature
+                        .and_then(|f| f.workflow_status.as_ref())
+                        .map(|s| &s.name)
+                        == Some(&status)
+                });
+                entries.extend(self.link_entries(path_string, features, names));
+            }
+
+            _ => (),
         };
+        if page > 0 {
+            return Ok(Some(entries));
+        }
+        if let Node::Release | Node::Epic | Node::Feature = node_of(path_string) {
+            if let Some((json, times)) = self.meta_file(&key(META_FILE)) {
+                entries.push(body_entry(META_FILE, &json, times));
+            }
+        }
+        // Threads are only fetched when comments.md is looked up, which its
+        // zero ttl makes happen on every open.
+        if let Node::Epic | Node::Feature = node_of(path_string) {
+            let times = self.dir_times(path_string);
+            let entry = match self.comment_threads.get(&key(COMMENTS_FILE)) {
+                Some((comments, times)) => body_entry(COMMENTS_FILE, comments, *times),
+                None => body_entry(COMMENTS_FILE, "", times),
+            };
+            entries.push(entry);
+        }
+        Ok(Some(entries))
+    }
 
-        println!("AFS readdir:  {} -> {}", path.display(), uri);
-        let aha = aha::Aha::new(
-            AHACONFIG.0.aha_domain.clone(),
-            AHACONFIG.0.aha_token.clone(),
-            AHACONFIG.0.workflow_email.clone(),
-            &AHACONFIG.1,
-        );
-        let path_string = path.display().to_string();
-        let mut count = path_string.matches("/").count();
-        if count == 3 {
-            return Box::new(
-                vec![
-                    Ok(basic_dir_entry("epics", 0o750)),
-                    Ok(basic_dir_entry("features", 0o750)),
-                ]
-                .into_iter(),
+    // Drops what an earlier listing of this directory found, so products,
+    // releases, epics, features and requirements removed in aha disappear
+    // here too.
+    fn forget_children(&mut self, path_string: &str) {
+        let is_child = |key: &String| split_parent(key).1 == path_string;
+        self.products.retain(|key, _| !is_child(key));
+        self.releases.retain(|key, _| !is_child(key));
+        self.epics.retain(|key, _| !is_child(key));
+        self.features.retain(|key, _| !is_child(key));
+        self.requirements.retain(|key, _| !is_child(key));
+        self.attachments.retain(|key, _| !is_child(key));
+        self.links.retain(|key, _| !is_child(key));
+    }
+
+    // The features of a release, or of every release of a product, by their
+    // directory under features/, listing the releases and their features.
+    fn scope_features(&mut self, scope: &str) -> Result<Vec<(String, String)>, LibcError> {
+        self.resolve(scope).ok_or(ENOENT)?;
+        let release_dirs = match node_of(scope) {
+            Node::Release => vec![scope.to_string()],
+            Node::Product => {
+                self.load_dir(scope)?;
+                self.releases
+                    .keys()
+                    .filter(|key| split_parent(key).1 == scope)
+                    .cloned()
+                    .collect()
+            }
+            _ => return Err(ENOENT),
+        };
+        let mut features = vec![];
+        for release_dir in release_dirs {
+            let features_dir = format!("{}/features", release_dir);
+            self.load_dir(&features_dir)?;
+            features.extend(
+                self.features
+                    .iter()
+                    .filter(|(key, _)| split_parent(key).1 == features_dir)
+                    .map(|(key, id)| (key.clone(), id.clone())),
             );
         }
-        let mut parent_dir = path_string.clone().to_string();
-        if count > 2 {
-            parent_dir = path_string.rsplitn(2, "/").last().unwrap().to_string();
+        Ok(features)
+    }
+
+    // Links in `dir` to each (feature directory, id), named like the
+    // directories they point at.
+    fn link_entries(
+        &mut self,
+        dir: &str,
+        features: Vec<(String, String)>,
+        names: &mut Names,
+    ) -> Vec<DirEntry> {
+        let mut entries = vec![];
+        for (feature_dir, feature_id) in features {
+            let times = match self.feature_values.get(&feature_id) {
+                Some(feature) => Times::of(feature),
+                None => continue,
+            };
+            let path = format!("{}/{}", dir, names.claim(split_parent(&feature_dir).0));
+            let (name, _) = split_parent(&path);
+            entries.push(link_entry(
+                &name,
+                &relative_path(&path, &feature_dir),
+                times,
+            ));
+            let link = Link {
+                id: feature_id,
+                target: feature_dir,
+            };
+            self.links.insert(path, link);
         }
-        dbg!(count, &parent_dir);
-        let parent = match count {
-            2 => self.products.get(&parent_dir.to_string()),
-            4 => self.releases.get(&parent_dir.to_string()),
-            _ => None,
+        entries
+    }
+
+    // Links in `dir` to features found anywhere in aha, at the directories
+    // they have under their releases.
+    fn found_links(&mut self, dir: &str, found: Vec<String>, names: &mut Names) -> Vec<DirEntry> {
+        let mut features = vec![];
+        for feature_id in found {
+            if let Some(feature_dir) = self.canonical_feature_dir(&feature_id) {
+                features.push((feature_dir, feature_id));
+            }
+        }
+        self.link_entries(dir, features, names)
+    }
+
+    // The directory a feature has under its release's features/, found
+    // through the release aha lists it in. Features outside any release have
+    // none.
+    fn canonical_feature_dir(&mut self, feature_id: &str) -> Option<String> {
+        let release = self.feature_values.get(feature_id)?.extra.get("release")?;
+        let release_id = release.get("id")?.as_str()?.to_string();
+        let product_id = release.get("product_id")?.as_str()?.to_string();
+        let product_dir = self.listed_path("/data", &product_id, |fs| &fs.products)?;
+        let release_dir = self.listed_path(&product_dir, &release_id, |fs| &fs.releases)?;
+        let features_dir = format!("{}/features", release_dir);
+        self.listed_path(&features_dir, feature_id, |fs| &fs.features)
+    }
+
+    // The path of the entry with `id` in `dir`, listing `dir` if it isn't
+    // known yet.
+    fn listed_path(
+        &mut self,
+        dir: &str,
+        id: &str,
+        ids: fn(&Self) -> &HashMap<String, String>,
+    ) -> Option<String> {
+        let find = |fs: &Self| {
+            ids(fs)
+                .iter()
+                .find(|(key, value)| *value == id && split_parent(key).1 == dir)
+                .map(|(key, _)| key.clone())
         };
-        let dir = aha.get_uri(&path_string, parent);
-        for x in &dir {
-            dbg!(&x);
-            let key = format!("{}/{}", path_string, x["name"].as_str().unwrap());
-            let value = x["id"].as_str().unwrap().to_string();
-            dbg!(&key, &value);
-            match count {
-                1 => {
-                    self.products.insert(key, value);
+        if let Some(path) = find(self) {
+            return Some(path);
+        }
+        self.load_dir(dir).ok()?;
+        find(self)
+    }
+
+    // Fetches what a /ref name stands for and links it to where it is in
+    // the tree, listing just the directories on the way there.
+    fn resolve_ref(&mut self, path_string: &str) -> Option<String> {
+        let (name, _) = split_parent(path_string);
+        let reference = name.strip_suffix(REF_DESCRIPTION_SUFFIX);
+        let (kind, key) = aha::Aha::type_from_name(reference.unwrap_or(&name))?;
+        if key != reference.unwrap_or(&name) {
+            return None;
+        }
+        let log = |err| println!("AFS ref error: {}: {}", key, err);
+        let (id, target) = match (kind.as_str(), reference) {
+            ("feature", _) => {
+                let feature = self.aha.feature(&key).map_err(log).ok()?;
+                let feature_id = feature.id.clone();
+                self.cache.fetched(&feature_key(&feature_id));
+                self.feature_values.insert(feature_id.clone(), feature);
+                let feature_dir = self.canonical_feature_dir(&feature_id)?;
+                match reference {
+                    Some(_) => (feature_id, format!("{}/{}", feature_dir, DESCRIPTION_FILE)),
+                    None => (feature_id, feature_dir),
                 }
+            }
+            ("requirement", None) => {
+                let requirement = self.aha.requirement(&key).map_err(log).ok()?;
+                let feature = requirement.extra.get("feature")?;
+                let feature_id = feature.get("id")?.as_str()?.to_string();
+                if !self.feature_values.contains_key(&feature_id) {
+                    let feature = self.aha.feature(&feature_id).map_err(log).ok()?;
+                    self.feature_values.insert(feature_id.clone(), feature);
+                }
+                let feature_dir = self.canonical_feature_dir(&feature_id)?;
+                let requirements_dir = format!("{}/{}", feature_dir, REQUIREMENTS_DIR);
+                let requirement_id = requirement.id.clone();
+                self.requirement_values
+                    .insert(requirement_id.clone(), requirement);
+                let target =
+                    self.listed_path(&requirements_dir, &requirement_id, |fs| &fs.requirements)?;
+                (requirement_id, target)
+            }
+            _ => return None,
+        };
+        let link = Link {
+            id: id.clone(),
+            target,
+        };
+        self.links.insert(path_string.to_string(), link);
+        Some(id)
+    }
+
+    // The dates of whatever a link points at.
+    fn link_times(&self, path_string: &str) -> Times {
+        let id = match self.links.get(path_string) {
+            Some(link) => &link.id,
+            None => return UNKNOWN_TIMES,
+        };
+        let feature = self.feature_values.get(id).map(Times::of);
+        let requirement = || self.requirement_values.get(id).map(Times::of);
+        feature.or_else(requirement).unwrap_or(UNKNOWN_TIMES)
+    }
+
+    // Whose features /mine or a /by-assignee/<email> directory lists.
+    fn assignee_of(&self, dir: &str) -> String {
+        match node_of(dir) {
+            Node::Mine => self.user_email.clone(),
+            _ => names::unescape(&split_parent(dir).0),
+        }
+    }
+
+    // A status directory exists while a feature in its product or release
+    // has that status, or after mkdir.
+    fn status_exists(&mut self, path_string: &str) -> bool {
+        if self.status_dirs.contains(path_string) {
+            return true;
+        }
+        let status = names::unescape(&split_parent(path_string).0);
+        let features = match self.scope_features(&view_scope(path_string)) {
+            Ok(features) => features,
+            Err(_) => return false,
+        };
+        features.iter().any(|(_, id)| {
+            let feature = self.feature_values.get(id);
+            feature
+                .and_then(|f| f.workflow_status.as_ref())
+                .map(|s| &s.name)
+                == Some(&status)
+        })
+    }
+
+    // The bytes of the descriptions a directory holds, as far as they are
+    // known: those of the features or epics directly in it, or for a feature
+    // directory its own.
+    fn dir_size(&self, path_string: &str) -> u64 {
+        if node_of(path_string) == Node::Feature {
+            return self
+                .features
+                .get(path_string)
+                .and_then(|id| self.feature_values.get(id))
+                .map_or(0, |feature| markdown::feature(feature).len() as u64);
+        }
+        let is_child = |key: &String| split_parent(key).1 == path_string;
+        let features = self
+            .features
+            .iter()
+            .filter(|(key, _)| is_child(key))
+            .filter_map(|(_, id)| self.feature_values.get(id))
+            .map(|feature| markdown::feature(feature).len() as u64);
+        let epics = self
+            .epics
+            .iter()
+            .filter(|(key, _)| is_child(key))
+            .filter_map(|(_, id)| self.epic_values.get(id))
+            .map(|epic| epic.description.body().len() as u64);
+        let requirements = self
+            .requirements
+            .iter()
+            .filter(|(key, _)| is_child(key))
+            .filter_map(|(_, id)| self.requirement_values.get(id))
+            .map(|requirement| requirement.description.body().len() as u64);
+        features.chain(epics).chain(requirements).sum()
+    }
+
+    // The dates of a product, release, epic or feature directory, or of the
+    // folders inside them (which take their owner's). Needs the path
+    // resolved.
+    fn dir_times(&self, path_string: &str) -> Times {
+        match node_of(path_string) {
+            Node::Epics | Node::Features | Node::Requirements | Node::Attachments => {
+                return self.dir_times(&split_parent(path_string).1);
+            }
+            Node::StatusView | Node::Status => return self.dir_times(&view_scope(path_string)),
+            _ => (),
+        }
+        let id = match self.cached_id(path_string) {
+            Some(id) => id,
+            None => return UNKNOWN_TIMES,
+        };
+        let times = match node_of(path_string) {
+            Node::Product => self.product_values.get(id).map(Times::of),
+            Node::Release => self.release_values.get(id).map(Times::of_release),
+            Node::Epic => self.epic_values.get(id).map(Times::of),
+            Node::Feature => self.feature_values.get(id).map(Times::of),
+            _ => None,
+        };
+        times.unwrap_or(UNKNOWN_TIMES)
+    }
+
+    // What a listed or looked up path is in aha, from the ids cached for it.
+    fn object_at(&self, path_string: &str) -> Option<Object> {
+        let (_, parent_dir) = split_parent(path_string);
+        let object = match node_of(path_string) {
+            Node::Product => Object::Product(self.products.get(path_string)?.clone()),
+            Node::Release => Object::Release(self.releases.get(path_string)?.clone()),
+            Node::Epics => Object::Epics(self.releases.get(&parent_dir)?.clone()),
+            Node::Features => Object::Features(self.releases.get(&parent_dir)?.clone()),
+            Node::EpicFile => Object::EpicFile(self.epics.get(epic_dir_of(path_string))?.clone()),
+            Node::Epic => Object::Epic(self.epics.get(path_string)?.clone()),
+            Node::Feature if node_of(&parent_dir) == Node::Epic => Object::EpicFeature {
+                epic: self.epics.get(&parent_dir)?.clone(),
+                feature: self.features.get(path_string)?.clone(),
+            },
+            Node::Feature => Object::Feature(self.features.get(path_string)?.clone()),
+            Node::Description => Object::Description(Box::new(self.object_at(&parent_dir)?)),
+            Node::Meta => Object::Meta(Box::new(self.object_at(&parent_dir)?)),
+            Node::Comments => Object::Comments(Box::new(self.object_at(&parent_dir)?)),
+            Node::Requirements => Object::Requirements(Box::new(self.object_at(&parent_dir)?)),
+            Node::Attachments => Object::Attachments(Box::new(self.object_at(&parent_dir)?)),
+            Node::Attachment => Object::Attachment {
+                feature: Box::new(self.object_at(&feature_dir_of(path_string))?),
+                id: self.attachments.get(path_string)?.clone(),
+            },
+            Node::Requirement => Object::Requirement {
+                feature: Box::new(self.object_at(&feature_dir_of(path_string))?),
+                id: self.requirements.get(path_string)?.clone(),
+            },
+            Node::StatusView => Object::StatusView(Box::new(self.object_at(&parent_dir)?)),
+            Node::Mine | Node::Assignees | Node::Searches | Node::Refs => {
+                Object::View(path_string[1..].to_string())
+            }
+            Node::Ref if self.links.contains_key(path_string) => {
+                Object::View(path_string[1..].to_string())
+            }
+            Node::AssigneeLink => Object::Link {
+                view: Box::new(Object::View(
+                    path_string[1..].split('/').next()?.to_string(),
+                )),
+                feature: self.links.get(path_string)?.id.clone(),
+            },
+            Node::StatusLink => Object::Link {
+                view: Box::new(self.object_at(&format!(
+                    "{}/{}",
+                    view_scope(path_string),
+                    BY_STATUS_DIR
+                ))?),
+                feature: self.links.get(path_string)?.id.clone(),
+            },
+            // each query is a view of its own
+            Node::SearchLink => Object::Link {
+                view: Box::new(Object::View(parent_dir[1..].to_string())),
+                feature: self.links.get(path_string)?.id.clone(),
+            },
+            _ => return None,
+        };
+        Some(object)
+    }
+
+    fn cached_id(&self, path_string: &str) -> Option<&String> {
+        match node_of(path_string) {
+            Node::Product => self.products.get(path_string),
+            Node::Release => self.releases.get(path_string),
+            Node::Epic => self.epics.get(path_string),
+            Node::Feature => self.features.get(path_string),
+            Node::Requirement => self.requirements.get(path_string),
+            Node::Attachment => self.attachments.get(path_string),
+            Node::StatusLink | Node::AssigneeLink | Node::SearchLink | Node::Ref => {
+                self.links.get(path_string).map(|link| &link.id)
+            }
+            _ => None,
+        }
+    }
+
+    // Finds the id of a product, release, epic, feature or requirement path,
+    // listing the parent directories on a cold cache.
+    fn resolve(&mut self, path_string: &str) -> Option<String> {
+        if let Some(id) = self.cached_id(path_string) {
+            return Some(id.clone());
+        }
+
+        // The directory holding this entry can only be listed once the
+        // product, release, epic or feature that owns it is known.
+        let (_, parent_dir) = split_parent(path_string);
+        if node_of(path_string) == Node::Ref {
+            return self.resolve_ref(path_string);
+        }
+        let owner = match node_of(path_string) {
+            // listing a status directory looks up its product or release
+            Node::Product | Node::StatusLink | Node::AssigneeLink | Node::SearchLink => None,
+            Node::Release => Some(parent_dir.clone()),
+            Node::Feature if node_of(&parent_dir) == Node::Epic => Some(parent_dir.clone()),
+            Node::Epic | Node::Feature | Node::Requirement | Node::Attachment => {
+                Some(split_parent(&parent_dir).1)
+            }
+            _ => return None,
+        };
+        if let Some(owner) = owner {
+            self.resolve(&owner)?;
+        }
+        self.load_dir(&parent_dir).ok()?;
 
-                2 => {
-                    self.releases.insert(key, value);
+        self.cached_id(path_string).cloned()
+    }
+
+    // Directories made with mkdir, and files made with touch or a shell
+    // redirect, in a release's "features" folder become new features in that
+    // release, a file's Markdown as the description. The next listing shows
+    // them as directories under the name the naming policy gives them.
+    fn create_feature(&mut self, path_string: &str, body: String) -> Result<(), LibcError> {
+        let (file_name, parent_dir) = split_parent(path_string);
+        if node_of(&parent_dir) != Node::Features || names::is_scratch(&file_name) {
+            return Err(libc::EACCES);
+        }
+        let name = names::unescape(&file_name);
+        let release_dir = split_parent(&parent_dir).1;
+        let release_id = self.resolve(&release_dir).ok_or(ENOENT)?;
+        let create = aha::FeatureCreate {
+            name,
+            release_id,
+            description: if body.is_empty() {
+                None
+            } else {
+                Some(markdown::description_html(&body))
+            },
+            custom_fields: None,
+        };
+        match self.aha.post_feature(&create) {
+            Ok(feature) => {
+                self.features
+                    .insert(path_string.to_string(), feature.id.clone());
+                self.cache.fetched(&feature_key(&feature.id));
+                self.feature_values.insert(feature.id.clone(), feature);
+                Ok(())
+            }
+            Err(err) => Err(api_error(err)),
+        }
+    }
+
+    // The feature behind a feature directory, fetched again once its body
+    // has outlived its ttl. If that fails the copy from the last listing is
+    // served.
+    fn feature_file(&mut self, path_string: &str) -> Option<&Feature> {
+        let feature_id = self.resolve(path_string)?;
+        if !self.cache.is_fresh(&feature_key(&feature_id), Kind::Body) {
+            match self.aha.feature(&feature_id) {
+                Ok(feature) => {
+                    self.feature_values.insert(feature_id.clone(), feature);
+                    self.cache.fetched(&feature_key(&feature_id));
                 }
+                Err(err) => println!("AFS refresh error: {}", err),
+            }
+        }
+        self.feature_values.get(&feature_id)
+    }
+
+    // The json and dates of the release, epic or feature a meta.json is in.
+    // Listings only ask aha for the fields the other files need, so this is
+    // the record fetched on its own, again once it is older than a body.
+    fn meta_file(&mut self, path_string: &str) -> Option<(String, Times)> {
+        let (_, owner) = split_parent(path_string);
+        let node = node_of(&owner);
+        let id = match node {
+            Node::Release | Node::Epic | Node::Feature => self.resolve(&owner)?,
+            _ => return None,
+        };
+        let cache_key = meta_key(&match node {
+            Node::Release => release_key(&id),
+            Node::Epic => epic_key(&id),
+            _ => feature_key(&id),
+        });
+        if self.cache.is_fresh(&cache_key, Kind::Body) {
+            if let Some(meta) = self.meta_files.get(path_string) {
+                return Some(meta.clone());
+            }
+        }
+        let fetched = match node {
+            Node::Release => self.aha.release(&id).map(|release| {
+                let times = Times::of_release(&release);
+                (serde_json::to_string_pretty(&release), times)
+            }),
+            Node::Epic => self
+                .aha
+                .epic(&id)
+                .map(|epic| (serde_json::to_string_pretty(&epic), Times::of(&epic))),
+            _ => self
+                .aha
+                .feature(&id)
+                .map(|feature| (serde_json::to_string_pretty(&feature), Times::of(&feature))),
+        };
+        let (json, times) = match fetched {
+            Ok(meta) => meta,
+            Err(err) => {
+                println!("AFS meta error: {}", err);
+                return None;
+            }
+        };
+        let meta = (json.ok()? + "\n", times);
+        self.meta_files
+            .insert(path_string.to_string(), meta.clone());
+        self.cache.fetched(&cache_key);
+        Some(meta)
+    }
+
+    // The comment thread of the epic or feature a comments.md is in, oldest
+    // first, dated by its owner's creation and the last comment. Fetched
+    // again once it is older than a body.
+    fn comments_file(&mut self, path_string: &str) -> Option<(String, Times)> {
+        let (_, owner) = split_parent(path_string);
+        let (base, id) = self.comments_owner(&owner)?;
+        let cache_key = comments_key(&format!("{}/{}", base, id));
+        if self.cache.is_fresh(&cache_key, Kind::Body) {
+            if let Some(thread) = self.comment_threads.get(path_string) {
+                return Some(thread.clone());
+            }
+        }
+        let mut comments: Vec<Comment> = match aha::all(self.aha.comments(base, &id)) {
+            Ok(comments) => comments,
+            Err(err) => {
+                println!("AFS comments error: {}", err);
+                return None;
+            }
+        };
+        comments.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        let created = self.dir_times(&owner).created;
+        let updated = comments.iter().map(|c| Times::of(c).updated).max();
+        let text = markdown::comments(&comments);
+        let times = Times {
+            created,
+            updated: updated.unwrap_or(created),
+        };
+        self.comment_threads
+            .insert(path_string.to_string(), (text.clone(), times));
+        self.cache.fetched(&cache_key);
+        Some((text, times))
+    }
+
+    // What aha calls the record an epic or feature directory stands for, and
+    // its id.
+    fn comments_owner(&mut self, owner: &str) -> Option<(&'static str, String)> {
+        let base = match node_of(owner) {
+            Node::Epic => "epic",
+            Node::Feature => "feature",
+            _ => return None,
+        };
+        Some((base, self.resolve(owner)?))
+    }
 
-                4 => {
-                    self.features.insert(key, value.clone());
-                    self.feature_values.insert(value, x.clone());
+    // comments.md only grows: whatever follows the thread as it was read
+    // into the open file is posted as a new comment, as the configured user.
+    fn post_comment(&mut self, path_string: &str, text: &str) -> Result<(), LibcError> {
+        // An empty thread is never read before it is appended to, so the
+        // thread to compare with is then the one the lookup fetched.
+        let thread = match self.comment_files.get(path_string) {
+            Some(thread) => thread.clone(),
+            None => self.comments_file(path_string).ok_or(ENOENT)?.0,
+        };
+        let added = text.strip_prefix(thread.as_str()).ok_or(libc::EACCES)?;
+        let added = added.trim().to_string();
+        if added.is_empty() {
+            return Ok(());
+        }
+        let (base, id) = self
+            .comments_owner(&split_parent(path_string).1)
+            .ok_or(ENOENT)?;
+        match self.aha.post_comment(base, &id, &markdown::to_html(&added)) {
+            Ok(_) => {
+                self.comment_files.remove(path_string);
+                self.comment_threads.remove(path_string);
+                Ok(())
+            }
+            Err(err) => Err(api_error(err)),
+        }
+    }
+
+    // Sends a change to aha and keeps the feature it hands back.
+    fn update_feature(
+        &mut self,
+        feature_id: &str,
+        update: &aha::FeatureUpdateCreate,
+    ) -> Result<(), LibcError> {
+        match self.aha.update_feature(feature_id, update) {
+            Ok(feature) => {
+                self.cache.fetched(&feature_key(feature_id));
+                self.feature_values.insert(feature_id.to_string(), feature);
+                Ok(())
+            }
+            Err(err) => Err(api_error(err)),
+        }
+    }
+
+    // Requirements are only fetched by listing their feature's folder.
+    fn requirement_file(&mut self, path_string: &str) -> Option<&Requirement> {
+        let requirement_id = self.resolve(path_string)?;
+        self.requirement_values.get(&requirement_id)
+    }
+
+    // Attachments are listed from their feature's description.
+    fn attachment_file(&mut self, path_string: &str) -> Option<&Attachment> {
+        let attachment_id = self.resolve(path_string)?;
+        self.attachment_values.get(&attachment_id)
+    }
+
+    // Sends a finished copy to aha as a new attachment of its feature.
+    fn upload(&mut self, path_string: &str, upload: &Upload) -> Result<(), LibcError> {
+        let (file_name, _) = split_parent(path_string);
+        let feature_id = self.resolve(&feature_dir_of(path_string)).ok_or(ENOENT)?;
+        let file = File::open(&upload.path).map_err(|_| libc::EIO)?;
+        let length = file.metadata().map_err(|_| libc::EIO)?.len();
+        let name = names::unescape(&file_name);
+        let attachment = self
+            .aha
+            .upload_attachment("feature", &feature_id, &name, file, length)
+            .map_err(api_error)?;
+        // the feature lists its attachments, so fetch it again
+        self.cache.invalidate(&feature_key(&feature_id));
+        self.attachments
+            .insert(path_string.to_string(), attachment.id.clone());
+        self.attachment_values
+            .insert(attachment.id.clone(), attachment);
+        Ok(())
+    }
+
+    // The epic behind an epic directory, refreshed the same way as
+    // features.
+    fn epic_file(&mut self, epic_dir: &str) -> Option<&Epic> {
+        let epic_id = self.resolve(epic_dir)?;
+        if !self.cache.is_fresh(&epic_key(&epic_id), Kind::Body) {
+            match self.aha.epic(&epic_id) {
+                Ok(epic) => {
+                    self.epic_values.insert(epic_id.clone(), epic);
+                    self.cache.fetched(&epic_key(&epic_id));
                 }
-                _ => (),
-            };
+                Err(err) => println!("AFS refresh error: {}", err),
+            }
+        }
+        self.epic_values.get(&epic_id)
+    }
+}
+
+impl<A: aha::Api> CachePolicy for AhaFS<A> {
+    // comments.md is listed without its thread, so it is looked up again
+    // every time for its size.
+    fn ttl(&self, path: &Path) -> Duration {
+        let path_string = path.display().to_string();
+        if node_of(&path_string) == Node::Comments {
+            return Duration::from_secs(0);
+        }
+        self.cache.ttl(cache_kind(&path_string))
+    }
+
+    // Directories are listed again by the driver; bodies are dropped here.
+    fn invalidate(&mut self, ino: u64, _path: &Path) {
+        if let Some(key) = self.objects.get(&ino).and_then(Object::body_key) {
+            self.cache.invalidate(&key);
+        }
+    }
+}
+
+impl<A: aha::Api> StableInodes for AhaFS<A> {
+    fn ino(&mut self, path: &Path) -> Option<u64> {
+        let object = self.object_at(&path.display().to_string())?;
+        let ino = object.ino();
+        self.objects.insert(ino, object);
+        Some(ino)
+    }
+}
+
+// Features carry their workflow fields as "user.aha.<field>" attributes, on
+// the feature directory and its description.md.
+const XATTR_PREFIX: &str = "user.aha.";
+const XATTR_FIELDS: [&str; 5] = ["status", "assignee", "reference", "url", "tags"];
+
+// The feature directory whose attributes a path has, if any.
+fn xattr_feature_dir(path: &Path) -> Option<String> {
+    let path_string = path.display().to_string();
+    match node_of(&path_string) {
+        Node::Feature => Some(path_string),
+        Node::Description => Some(feature_dir_of(&path_string)),
+        _ => None,
+    }
+}
+
+impl<A: aha::Api> ExtendedAttributes for AhaFS<A> {
+    fn xattrs(&mut self, path: &Path) -> Result<Vec<String>, LibcError> {
+        if xattr_feature_dir(path).is_none() {
+            return Ok(vec![]);
         }
-        let iter = dir
+        let names = XATTR_FIELDS
             .iter()
-            .map(|child| Ok(build_dir_entry(&child, &path_string)));
-        let hack = iter.collect::<Vec<_>>().into_iter();
-        Box::new(hack)
+            .map(|field| format!("{}{}", XATTR_PREFIX, field));
+        Ok(names
+            .filter(|name| self.xattr(path, name).is_ok())
+            .collect())
     }
 
-    fn lookup(&mut self, path: &Path) -> Result<Metadata, LibcError> {
-        if valid_connector(&path) {
-            let uri = path_to_uri(&path)?;
-            println!("AFS lookup: {} -> {}", path.display(), uri);
-            /*
-            match self.client.data(&uri).into_type() {
-                Ok(data_item) => Ok(build_dir_entry(&data_item).metadata),
-                Err(err) => eio!("AFS lookup error: {}", err),
+    fn xattr(&mut self, path: &Path, name: &str) -> Result<Vec<u8>, LibcError> {
+        let feature_dir = xattr_feature_dir(path).ok_or(libc::ENODATA)?;
+        let feature = self.feature_file(&feature_dir).ok_or(ENOENT)?;
+        let value = match name.strip_prefix(XATTR_PREFIX) {
+            Some("status") => feature.workflow_status.as_ref().map(|s| s.name.clone()),
+            Some("assignee") => feature.assigned_to_user.as_ref().map(|u| u.email.clone()),
+            Some("reference") => feature.reference_num.clone(),
+            Some("url") => feature.url.clone(),
+            Some("tags") => Some(feature.tags.join(",")),
+            _ => None,
+        };
+        value.map(String::into_bytes).ok_or(libc::ENODATA)
+    }
+
+    // Status and assignee go to aha like any other feature update; the
+    // reference, url and tags are read only.
+    fn set_xattr(&mut self, path: &Path, name: &str, value: &[u8]) -> Result<(), LibcError> {
+        println!("AFS setxattr: {} {}", path.display(), name);
+        let feature_dir = xattr_feature_dir(path).ok_or(libc::ENOTSUP)?;
+        let value = String::from_utf8(value.to_vec()).map_err(|_| libc::EINVAL)?;
+        let value = value.trim().to_string();
+        let mut update = aha::FeatureUpdateCreate::default();
+        match name.strip_prefix(XATTR_PREFIX) {
+            Some("status") => {
+                update.workflow_status = Some(aha::WorkflowStatusUpdate { name: value })
+            }
+            Some("assignee") => update.assigned_to_user = Some(value),
+            Some("reference") | Some("url") | Some("tags") => return Err(libc::EACCES),
+            _ => return Err(libc::ENOTSUP),
+        }
+        let feature_id = self.resolve(&feature_dir).ok_or(ENOENT)?;
+        self.update_feature(&feature_id, &update)
+    }
+}
+
+impl<A: aha::Api> NetworkFilesystem for AhaFS<A> {
+    fn readdir(
+        &mut self,
+        path: &Path,
+        handle: u64,
+        page: usize,
+    ) -> Result<Option<Vec<DirEntry>>, LibcError> {
+        let uri = match path_to_uri(path) {
+            Ok(u) => u,
+            Err(_) if page > 0 => return Ok(None),
+            Err(_) => {
+                // The default root listing
+                let entries = vec![
+                    basic_dir_entry("/data", 0o550, 0, UNKNOWN_TIMES),
+                    basic_dir_entry(MINE_DIR, 0o550, 0, UNKNOWN_TIMES),
+                    basic_dir_entry(BY_ASSIGNEE_DIR, 0o550, 0, UNKNOWN_TIMES),
+                    basic_dir_entry(SEARCH_DIR, 0o550, 0, UNKNOWN_TIMES),
+                    basic_dir_entry(REF_DIR, 0o550, 0, UNKNOWN_TIMES),
+                ];
+                return Ok(Some(entries));
             }
-            */
+        };
 
-            Err(ENOENT)
-        } else {
-            Err(ENOENT)
+        println!("AFS readdir:  {} -> {} page {}", path.display(), uri, page);
+        let path_string = path.display().to_string();
+        let mut names = match self.listings.remove(&handle) {
+            Some(names) if page > 0 => names,
+            _ => self.names_for(&path_string),
+        };
+        let listed = self.load_page(&path_string, page, &mut names)?;
+        if listed.is_some() {
+            self.listings.insert(handle, names);
+        }
+        Ok(listed)
+    }
+
+    fn releasedir(&mut self, handle: u64) {
+        self.listings.remove(&handle);
+    }
+
+    fn lookup(&mut self, path: &Path) -> Result<Metadata, LibcError> {
+        let path_string = path.display().to_string();
+        if !valid_connector(path) && !is_top_view(&path_string) {
+            return Err(ENOENT);
+        }
+        let uri = path_to_uri(path)?;
+        println!("AFS lookup: {} -> {}", path.display(), uri);
+        let (name, parent_dir) = split_parent(&path_string);
+        match node_of(&path_string) {
+            Node::Data => Ok(basic_dir_entry("/data", 0o550, 0, UNKNOWN_TIMES).metadata),
+            Node::Mine | Node::Assignees | Node::Searches | Node::Search | Node::Refs => {
+                Ok(basic_dir_entry(&name, 0o550, 0, UNKNOWN_TIMES).metadata)
+            }
+            // anyone can be moved to, whether or not they have features yet
+            Node::Assignee if name.contains('@') => {
+                Ok(basic_dir_entry(&name, 0o750, 0, UNKNOWN_TIMES).metadata)
+            }
+            Node::Product | Node::Release => match self.resolve(&path_string) {
+                Some(_) => {
+                    let times = self.dir_times(&path_string);
+                    Ok(basic_dir_entry(&path_string, 0o750, 0, times).metadata)
+                }
+                None => Err(ENOENT),
+            },
+            node @ Node::Epics
+            | node @ Node::Features
+            | node @ Node::Requirements
+            | node @ Node::Attachments => {
+                self.resolve(&parent_dir).ok_or(ENOENT)?;
+                let times = self.dir_times(&path_string);
+                let size = self.dir_size(&path_string);
+                let perm = if node == Node::Requirements {
+                    0o550
+                } else {
+                    0o750
+                };
+                Ok(basic_dir_entry(&name, perm, size, times).metadata)
+            }
+            Node::Epic | Node::Feature => {
+                self.resolve(&path_string).ok_or(ENOENT)?;
+                let times = self.dir_times(&path_string);
+                let size = self.dir_size(&path_string);
+                Ok(basic_dir_entry(&name, 0o750, size, times).metadata)
+            }
+            Node::EpicFile => {
+                let epic = self.epic_file(epic_dir_of(&path_string)).ok_or(ENOENT)?;
+                Ok(body_entry(&name, epic.description.body(), Times::of(epic)).metadata)
+            }
+            Node::Description => {
+                let feature = self.feature_file(&parent_dir).ok_or(ENOENT)?;
+                let body = markdown::feature(feature);
+                Ok(body_entry(&name, &body, Times::of(feature)).metadata)
+            }
+            Node::Requirement => {
+                let requirement = self.requirement_file(&path_string).ok_or(ENOENT)?;
+                let body = requirement.description.body();
+                Ok(body_entry(&name, body, Times::of(requirement)).metadata)
+            }
+            Node::Meta => {
+                let (json, times) = self.meta_file(&path_string).ok_or(ENOENT)?;
+                Ok(body_entry(&name, &json, times).metadata)
+            }
+            Node::Attachment => {
+                if let Some(upload) = self.uploads.get(&path_string) {
+                    let size = upload.file.metadata().map_or(0, |m| m.len());
+                    return Ok(metadata(FileType::RegularFile, 0o640, size, UNKNOWN_TIMES));
+                }
+                let attachment = self.attachment_file(&path_string).ok_or(ENOENT)?;
+                let size = attachment.file_size.unwrap_or(0);
+                Ok(metadata(
+                    FileType::RegularFile,
+                    0o440,
+                    size,
+                    Times::of(attachment),
+                ))
+            }
+            Node::Comments => {
+                let (comments, times) = self.comments_file(&path_string).ok_or(ENOENT)?;
+                Ok(body_entry(&name, &comments, times).metadata)
+            }
+            Node::StatusView => {
+                self.resolve(&view_scope(&path_string)).ok_or(ENOENT)?;
+                let times = self.dir_times(&path_string);
+                Ok(basic_dir_entry(&name, 0o750, 0, times).metadata)
+            }
+            Node::Status => {
+                if !self.status_exists(&path_string) {
+                    return Err(ENOENT);
+                }
+                let times = self.dir_times(&path_string);
+                Ok(basic_dir_entry(&name, 0o750, 0, times).metadata)
+            }
+            Node::StatusLink | Node::AssigneeLink | Node::SearchLink => {
+                self.resolve(&path_string).ok_or(ENOENT)?;
+                let target = relative_path(&path_string, &self.links[&path_string].target);
+                Ok(link_entry(&name, &target, self.link_times(&path_string)).metadata)
+            }
+            // fetched again on every lookup, in case the feature moved
+            Node::Ref => {
+                self.resolve_ref(&path_string).ok_or(ENOENT)?;
+                let target = relative_path(&path_string, &self.links[&path_string].target);
+                Ok(link_entry(&name, &target, self.link_times(&path_string)).metadata)
+            }
+            Node::Root | Node::Assignee | Node::Unknown => Err(ENOENT),
         }
     }
 
     fn read(&mut self, path: &Path, buffer: &mut Vec<u8>) -> Result<usize, LibcError> {
-        let uri = path_to_uri(&path)?;
+        let uri = path_to_uri(path)?;
         println!("AFS read: {} -> {}", path.display(), uri);
-        match self.features.get(&path.display().to_string()) {
-            Some(feature_id) => {
-                let feature = self.feature_values.get(feature_id).unwrap();
-                let bytes = feature["description"]["body"]
-                    .as_str()
-                    .unwrap()
-                    .as_bytes()
-                    .read_to_end(buffer)
-                    .expect("failed to read response bytes");
-                Ok(bytes as usize)
+        let path_string = path.display().to_string();
+        let body = match node_of(&path_string) {
+            Node::Description => {
+                let feature_dir = feature_dir_of(&path_string);
+                self.feature_file(&feature_dir).map(markdown::feature)
+            }
+            Node::EpicFile => self
+                .epic_file(epic_dir_of(&path_string))
+                .map(|e| e.description.body().to_string()),
+            Node::Requirement => self
+                .requirement_file(&path_string)
+                .map(|r| r.description.body().to_string()),
+            Node::Meta => self.meta_file(&path_string).map(|(json, _)| json),
+            Node::Comments => {
+                let thread = self.comments_file(&path_string).map(|(text, _)| text);
+                if let Some(text) = &thread {
+                    self.comment_files.insert(path_string.clone(), text.clone());
+                }
+                thread
             }
-            None => eio!("AFS read error: {}", libc::EPERM),
+            _ => None,
+        };
+        match body {
+            Some(body) => {
+                buffer.extend_from_slice(body.as_bytes());
+                Ok(buffer.len())
+            }
+            None => Err(ENOENT),
         }
     }
+
+    // Called by the driver once the last handle on a modified file is closed
+    // (or on fsync) with the whole buffered file.
+    fn write(&mut self, path: &Path, data: &[u8]) -> Result<(), LibcError> {
+        let uri = path_to_uri(path)?;
+        println!("AFS write: {} -> {}", path.display(), uri);
+        let path_string = path.display().to_string();
+        let node = node_of(&path_string);
+        if node != Node::Description && node != Node::Comments && node != Node::Feature {
+            return Err(libc::EACCES);
+        }
+        let text = String::from_utf8(data.to_vec()).map_err(|_| libc::EINVAL)?;
+        let feature_id = match node {
+            Node::Comments => return self.post_comment(&path_string, &text),
+            // A file made with touch or a redirect in a features folder, or
+            // written again before the next listing shows it as a directory.
+            Node::Feature => match self.resolve(&path_string) {
+                Some(feature_id) => feature_id,
+                None => return self.create_feature(&path_string, text),
+            },
+            _ => self.resolve(&feature_dir_of(&path_string)).ok_or(ENOENT)?,
+        };
+
+        let update = aha::FeatureUpdateCreate {
+            description: Some(markdown::description_html(&text)),
+            assigned_to_user: None,
+            custom_fields: None,
+            workflow_status: None,
+        };
+        self.update_feature(&feature_id, &update)
+    }
+
+    // Files only become something in aha in a release's features/ (a new
+    // feature, made on close with what was written as its description) and
+    // in a feature's attachments/. Editors' scratch files are refused there
+    // too, before anything is written to them.
+    fn mknod(&mut self, path: &Path) -> Result<(), LibcError> {
+        let path_string = path.display().to_string();
+        let (file_name, parent_dir) = split_parent(&path_string);
+        match node_of(&parent_dir) {
+            _ if names::is_scratch(&file_name) => Err(libc::EACCES),
+            Node::Features | Node::Attachments => {
+                self.resolve(&split_parent(&parent_dir).1).ok_or(ENOENT)?;
+                Ok(())
+            }
+            _ => Err(libc::EACCES),
+        }
+    }
+
+    fn mkdir(&mut self, path: &Path) -> Result<(), LibcError> {
+        let uri = path_to_uri(path)?;
+        println!("AFS mkdir: {} -> {}", path.display(), uri);
+        let path_string = path.display().to_string();
+        if node_of(&path_string) == Node::Status {
+            self.status_dirs.insert(path_string);
+            return Ok(());
+        }
+        self.create_feature(&path_string, String::new())
+    }
+}
+
+impl<A: aha::Api> Streams for AhaFS<A> {
+    fn is_streamed(&mut self, path: &Path) -> bool {
+        node_of(&path.display().to_string()) == Node::Attachment
+    }
+
+    // Carries on with the open download when the read follows the last one,
+    // and starts another from `offset` when it doesn't.
+    fn read_at(&mut self, path: &Path, offset: u64, size: u32) -> Result<Vec<u8>, LibcError> {
+        let path_string = path.display().to_string();
+        let follows = self.downloads.get(&path_string).map(|d| d.offset) == Some(offset);
+        if !follows {
+            let attachment = self.attachment_file(&path_string).ok_or(ENOENT)?;
+            let url = attachment.download_url.clone().ok_or(ENOENT)?;
+            let body = self.aha.download(&url, offset).map_err(api_error)?;
+            self.downloads
+                .insert(path_string.clone(), Download { offset, body });
+        }
+        let download = self.downloads.get_mut(&path_string).ok_or(libc::EIO)?;
+        let mut chunk = Vec::with_capacity(size as usize);
+        download
+            .body
+            .by_ref()
+            .take(u64::from(size))
+            .read_to_end(&mut chunk)
+            .map_err(|_| libc::EIO)?;
+        download.offset += chunk.len() as u64;
+        Ok(chunk)
+    }
+
+    // Attachments already in aha can't be changed, only new ones copied in.
+    fn write_at(&mut self, path: &Path, offset: u64, data: &[u8]) -> Result<(), LibcError> {
+        let path_string = path.display().to_string();
+        if !self.uploads.contains_key(&path_string) {
+            if self.attachments.contains_key(&path_string) {
+                return Err(libc::EACCES);
+            }
+            let spool = std::env::temp_dir().join(format!(
+                "ahafs-upload-{}-{}",
+                std::process::id(),
+                self.uploads_started
+            ));
+            self.uploads_started += 1;
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&spool)
+                .map_err(|_| libc::EIO)?;
+            let upload = Upload { file, path: spool };
+            self.uploads.insert(path_string.clone(), upload);
+        }
+        let upload = &self.uploads[&path_string];
+        upload
+            .file
+            .write_all_at(data, offset)
+            .map_err(|_| libc::EIO)
+    }
+
+    fn close(&mut self, path: &Path) -> Result<(), LibcError> {
+        let path_string = path.display().to_string();
+        self.downloads.remove(&path_string);
+        let upload = match self.uploads.remove(&path_string) {
+            Some(upload) => upload,
+            None => return Ok(()),
+        };
+        println!("AFS upload: {}", path_string);
+        let result = self.upload(&path_string, &upload);
+        let _ = fs::remove_file(&upload.path);
+        result
+    }
+}
+
+impl<A: aha::Api> Links for AhaFS<A> {
+    fn readlink(&mut self, path: &Path) -> Result<PathBuf, LibcError> {
+        let path_string = path.display().to_string();
+        self.resolve(&path_string).ok_or(ENOENT)?;
+        let link = self.links.get(&path_string).ok_or(ENOENT)?;
+        Ok(PathBuf::from(relative_path(&path_string, &link.target)))
+    }
+}
+
+impl<A: aha::Api> Renames for AhaFS<A> {
+    // Moving a link to another status directory moves the feature to that
+    // workflow status, and moving one to another assignee's directory (or
+    // /mine) assigns it to them. Nothing else can be renamed.
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<(), LibcError> {
+        println!("AFS rename: {} -> {}", from.display(), to.display());
+        let (from, to) = (from.display().to_string(), to.display().to_string());
+        let (_, to_dir) = split_parent(&to);
+        let update = match (node_of(&from), node_of(&to)) {
+            (Node::StatusLink, Node::StatusLink) => {
+                let status = names::unescape(&split_parent(&to_dir).0);
+                aha::FeatureUpdateCreate {
+                    workflow_status: Some(aha::WorkflowStatusUpdate { name: status }),
+                    ..Default::default()
+                }
+            }
+            (Node::AssigneeLink, Node::AssigneeLink) => aha::FeatureUpdateCreate {
+                assigned_to_user: Some(self.assignee_of(&to_dir)),
+                ..Default::default()
+            },
+            _ => return Err(libc::EACCES),
+        };
+        let feature_id = self.resolve(&from).ok_or(ENOENT)?;
+        self.update_feature(&feature_id, &update)?;
+        if let Some(link) = self.links.remove(&from) {
+            self.links.insert(to, link);
+        }
+        Ok(())
+    }
+}
+
+// /mine, /by-assignee, /search and /ref sit next to /data.
+fn is_top_view(path_string: &str) -> bool {
+    matches!(
+        node_of(path_string),
+        Node::Mine
+            | Node::Assignees
+            | Node::Assignee
+            | Node::AssigneeLink
+            | Node::Searches
+            | Node::Search
+            | Node::SearchLink
+            | Node::Refs
+            | Node::Ref
+    )
 }
 
 pub fn valid_connector(path: &Path) -> bool {
@@ -384,9 +2006,430 @@ pub fn uri_to_path(uri: &str) -> PathBuf {
         .fold(Path::new("/").to_owned(), |acc, p| acc.join(Path::new(p)))
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mnt = "/tmp/ahafs".to_string();
-    let options = netfuse::MountOptions::new(&mnt);
-    AhaFS::mount(options);
+// Runs the PR -> aha sync for every repo in ~/.aha_workflow (or the
+// WORKFLOW_REPO/WORKFLOW_LOGIN from the environment when none are set) and
+// prints what changed, or what would change with --dryrun.
+fn sync_prs(
+    aha: &aha::Aha,
+    env: &Env,
+    opt: &Opt,
+    repos: &[RepoConfig],
+) -> Result<(), Box<dyn Error>> {
+    let fallback = vec![RepoConfig {
+        name: env.workflow_repo.clone(),
+        username: env.workflow_login.clone(),
+        labels: None,
+    }];
+    let repos = if repos.is_empty() { &fallback } else { repos };
+
+    let mut changes = vec![];
+    for repo in repos {
+        if let Some(only) = &opt.repo {
+            if &repo.name != only {
+                continue;
+            }
+        }
+        let github_config = github::GithubEnv {
+            github_api_token: env.github_api_token.clone(),
+            workflow_repo: repo.name.clone(),
+            workflow_login: repo.username.clone(),
+            silent: opt.silent,
+            verbose: opt.verbose,
+            pr_table: opt.pr_status,
+        };
+        let mut prs = github::prs(github_config.clone(), true)?;
+        if opt.closed {
+            prs.append(&mut github::prs(github_config, false)?);
+        }
+        for pr in prs {
+            if let Some(change) = aha.sync_pr(pr, repo.labels.clone())? {
+                changes.push((repo.name.clone(), change));
+            }
+        }
+    }
+
+    if !opt.silent {
+        let verb = if opt.dry_run {
+            "would update"
+        } else {
+            "updated"
+        };
+        if changes.is_empty() {
+            println!("nothing to update");
+        }
+        for (repo, change) in changes {
+            println!(
+                "{} {} ({}#{}): {}",
+                verb,
+                change.key,
+                repo,
+                change.pr_number,
+                change.update.summary()
+            );
+        }
+    }
+    Ok(())
+}
+
+// Mounts aha through the on-disk store, with a second client refreshing it in
+// the background.
+fn mount_aha(
+    aha: aha::Aha<'static>,
+    env: &Env,
+    opt: &'static Opt,
+    options: &MountOptions,
+) -> Result<(), Box<dyn Error>> {
+    let dir = store::Store::default_dir().ok_or("could not find a cache directory")?;
+    let store = store::Store::open(dir)?;
+    let background = aha::Aha::new(
+        env.aha_domain.clone(),
+        env.aha_token.clone(),
+        env.workflow_email.clone(),
+        opt,
+    );
+    let api = store::Stored::new(aha, store, opt.offline, background);
+    let naming = opt.naming.unwrap_or_default();
+    AhaFS::new(api, naming, env.workflow_email.clone()).mount(options)?;
     Ok(())
 }
+
+fn main() {
+    let (env, opt, repos) = match load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("rust-aha-fs: {}", err);
+            std::process::exit(1);
+        }
+    };
+    // Lives as long as the process; the store's background client needs it
+    // on another thread.
+    let opt: &'static Opt = Box::leak(Box::new(opt));
+    // A mount waits for SIGINT/SIGTERM on a thread of its own, so they are
+    // blocked before anything else starts one: the http clients and the
+    // store's revalidation would otherwise take them and die mounted.
+    if let None | Some(Command::Mount { .. }) = &opt.cmd {
+        mount::block_signals();
+    }
+    let aha = aha::Aha::new(
+        env.aha_domain.clone(),
+        env.aha_token.clone(),
+        env.workflow_email.clone(),
+        opt,
+    );
+
+    let result: Result<(), Box<dyn Error>> = match &opt.cmd {
+        None => mount_aha(aha, &env, opt, &MountOptions::new("/tmp/ahafs", vec![])),
+        Some(Command::Mount { dir, options }) => {
+            mount_aha(aha, &env, opt, &MountOptions::new(dir, options.clone()))
+        }
+        Some(Command::Unmount { dir }) => mount::unmount(dir).map_err(|e| e.into()),
+        Some(Command::SyncPrs) => sync_prs(&aha, &env, opt, &repos),
+        Some(Command::Generate) => aha.generate().map(|_| ()).map_err(|e| e.into()),
+    };
+    if let Err(err) = result {
+        eprintln!("rust-aha-fs: {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aha::{AhaError, FeatureCreate, FeatureSearch, FeatureUpdateCreate, PageIter};
+    use models::User;
+    use serde_json::json;
+    use std::cell::RefCell;
+    use std::io::Read;
+    use std::iter;
+
+    // Answers listings from what it was built with; everything else is
+    // missing.
+    #[derive(Default)]
+    struct Fake {
+        products: Vec<Product>,
+        releases: Vec<Release>,
+        // one Vec per page
+        features: Vec<Vec<Feature>>,
+        // the bodies of the comments posted
+        posted: RefCell<Vec<String>>,
+    }
+
+    fn missing<T>(id: &str) -> Result<T, AhaError> {
+        Err(AhaError::NotFound(id.to_string()))
+    }
+
+    impl aha::Api for Fake {
+        fn products(&self) -> PageIter<'_, Product> {
+            Box::new(iter::once(Ok(self.products.clone())))
+        }
+
+        fn releases(&self, _product_id: &str) -> PageIter<'_, Release> {
+            Box::new(iter::once(Ok(self.releases.clone())))
+        }
+
+        fn features(&self, _release_id: &str) -> PageIter<'_, Feature> {
+            Box::new(self.features.clone().into_iter().map(Ok))
+        }
+
+        fn epics(&self, _release_id: &str) -> PageIter<'_, Epic> {
+            Box::new(iter::empty())
+        }
+
+        fn epic_features(&self, _epic_id: &str) -> PageIter<'_, Feature> {
+            Box::new(iter::empty())
+        }
+
+        fn requirements(&self, _feature_id: &str) -> PageIter<'_, Requirement> {
+            Box::new(iter::empty())
+        }
+
+        fn users(&self) -> PageIter<'_, User> {
+            Box::new(iter::empty())
+        }
+
+        fn assigned_features(&self, _email: &str) -> PageIter<'_, Feature> {
+            Box::new(iter::empty())
+        }
+
+        fn search_features(&self, _search: &FeatureSearch) -> PageIter<'_, Feature> {
+            Box::new(iter::empty())
+        }
+
+        fn release(&self, release_id: &str) -> Result<Release, AhaError> {
+            missing(release_id)
+        }
+
+        // A listed feature with a field listings don't ask for.
+        fn feature(&self, feature_id: &str) -> Result<Feature, AhaError> {
+            let mut listed = self.features.iter().flatten();
+            match listed.find(|feature| feature.id == feature_id) {
+                Some(feature) => {
+                    let mut feature = feature.clone();
+                    feature
+                        .extra
+                        .insert("workflow_kind".to_string(), json!("New"));
+                    Ok(feature)
+                }
+                None => missing(feature_id),
+            }
+        }
+
+        fn epic(&self, epic_id: &str) -> Result<Epic, AhaError> {
+            missing(epic_id)
+        }
+
+        fn requirement(&self, requirement_id: &str) -> Result<Requirement, AhaError> {
+            missing(requirement_id)
+        }
+
+        fn update_feature(
+            &self,
+            feature_id: &str,
+            _update: &FeatureUpdateCreate,
+        ) -> Result<Feature, AhaError> {
+            missing(feature_id)
+        }
+
+        fn post_feature(&self, feature: &FeatureCreate) -> Result<Feature, AhaError> {
+            missing(&feature.name)
+        }
+
+        fn comments(&self, _base: &str, _id: &str) -> PageIter<'_, Comment> {
+            Box::new(iter::empty())
+        }
+
+        fn post_comment(&self, _base: &str, _id: &str, body: &str) -> Result<Comment, AhaError> {
+            self.posted.borrow_mut().push(body.to_string());
+            Ok(serde_json::from_value(json!({"id": "c1", "body": body})).unwrap())
+        }
+
+        fn download(&self, url: &str, _offset: u64) -> Result<Box<dyn Read>, AhaError> {
+            missing(url)
+        }
+
+        fn upload_attachment(
+            &self,
+            _base: &str,
+            id: &str,
+            _file_name: &str,
+            _file: File,
+            _length: u64,
+        ) -> Result<Attachment, AhaError> {
+            missing(id)
+        }
+    }
+
+    fn feature(id: &str, reference: &str, name: &str) -> Feature {
+        serde_json::from_value(json!({"id": id, "reference_num": reference, "name": name})).unwrap()
+    }
+
+    // One product with one release, whose features come in two pages.
+    fn fs() -> AhaFS<Fake> {
+        let fake = Fake {
+            products: vec![serde_json::from_value(json!({"id": "p1", "name": "App"})).unwrap()],
+            releases: vec![serde_json::from_value(json!({"id": "r1", "name": "2.1"})).unwrap()],
+            features: vec![
+                vec![
+                    feature("f1", "ENG-1", "Login"),
+                    feature("f2", "ENG-2", "Logout"),
+                ],
+                vec![feature("f3", "ENG-2", "Logout")],
+            ],
+            ..Fake::default()
+        };
+        AhaFS::new(fake, Naming::RefAndName, "me@example.com".to_string())
+    }
+
+    fn names(entries: Vec<DirEntry>) -> Vec<String> {
+        entries
+            .into_iter()
+            .map(|entry| entry.filename.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn mount_options_leave_the_directory_alone() {
+        let args = [
+            "rust-aha-fs",
+            "mount",
+            "-o",
+            "allow_other",
+            "-o",
+            "ro",
+            "/mnt/aha",
+        ];
+        match Opt::from_iter_safe(&args).unwrap().cmd {
+            Some(Command::Mount { dir, options }) => {
+                assert_eq!(dir, PathBuf::from("/mnt/aha"));
+                assert_eq!(options, ["allow_other", "ro"]);
+            }
+            cmd => panic!("parsed as {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn paths_are_told_apart() {
+        let features = "/data/App/2.1/features";
+        let feature = "/data/App/2.1/features/ENG-1 Login";
+        assert!(node_of("/") == Node::Root);
+        assert!(node_of("/data") == Node::Data);
+        assert!(node_of("/data/App") == Node::Product);
+        assert!(node_of("/data/App/2.1") == Node::Release);
+        assert!(node_of(features) == Node::Features);
+        assert!(node_of(feature) == Node::Feature);
+        assert!(node_of(&format!("{}/{}", feature, DESCRIPTION_FILE)) == Node::Description);
+        assert!(node_of(&format!("{}/{}", feature, META_FILE)) == Node::Meta);
+        assert!(node_of(&format!("{}/{}", feature, COMMENTS_FILE)) == Node::Comments);
+        assert!(node_of(&format!("{}/{}", feature, REQUIREMENTS_DIR)) == Node::Requirements);
+        assert!(node_of("/mine") == Node::Mine);
+        assert!(node_of("/by-assignee/someone@example.com") == Node::Assignee);
+    }
+
+    #[test]
+    fn data_lists_products_and_releases() {
+        let mut fs = fs();
+        assert_eq!(names(fs.load_dir("/data").unwrap()), ["App"]);
+        assert_eq!(
+            names(fs.load_dir("/data/App").unwrap()),
+            ["2.1", BY_STATUS_DIR]
+        );
+        assert_eq!(fs.releases["/data/App/2.1"], "r1");
+    }
+
+    #[test]
+    fn features_are_listed_across_pages_with_unique_names() {
+        let mut fs = fs();
+        fs.load_dir("/data").unwrap();
+        fs.load_dir("/data/App").unwrap();
+        let features = fs.load_dir("/data/App/2.1/features").unwrap();
+        assert_eq!(
+            names(features),
+            ["ENG-1 Login", "ENG-2 Logout", "ENG-2 Logout (2)"]
+        );
+        assert_eq!(fs.features["/data/App/2.1/features/ENG-2 Logout (2)"], "f3");
+    }
+
+    #[test]
+    fn readdir_hands_on_a_page_at_a_time() {
+        let mut fs = fs();
+        fs.load_dir("/data").unwrap();
+        fs.load_dir("/data/App").unwrap();
+        let path = Path::new("/data/App/2.1/features");
+        let first = fs.readdir(path, 7, 0).unwrap().unwrap();
+        assert_eq!(names(first), ["ENG-1 Login", "ENG-2 Logout"]);
+        // the handle still knows the names the first page took
+        let second = fs.readdir(path, 7, 1).unwrap().unwrap();
+        assert_eq!(names(second), ["ENG-2 Logout (2)"]);
+        assert!(fs.readdir(path, 7, 2).unwrap().is_none());
+        fs.releasedir(7);
+        assert!(fs.listings.is_empty());
+    }
+
+    #[test]
+    fn a_feature_lists_its_files() {
+        let mut fs = fs();
+        fs.load_dir("/data").unwrap();
+        fs.load_dir("/data/App").unwrap();
+        fs.load_dir("/data/App/2.1/features").unwrap();
+        let files = fs.load_dir("/data/App/2.1/features/ENG-1 Login").unwrap();
+        assert_eq!(
+            names(files),
+            [
+                DESCRIPTION_FILE,
+                REQUIREMENTS_DIR,
+                ATTACHMENTS_DIR,
+                META_FILE,
+                COMMENTS_FILE
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_json_is_the_whole_record() {
+        let mut fs = fs();
+        fs.load_dir("/data").unwrap();
+        fs.load_dir("/data/App").unwrap();
+        fs.load_dir("/data/App/2.1/features").unwrap();
+        let meta = format!("/data/App/2.1/features/ENG-1 Login/{}", META_FILE);
+        let (json, _) = fs.meta_file(&meta).unwrap();
+        assert!(json.contains("workflow_kind"));
+    }
+
+    #[test]
+    fn the_first_comment_is_appended_to_an_empty_thread() {
+        let mut fs = fs();
+        fs.load_dir("/data").unwrap();
+        fs.load_dir("/data/App").unwrap();
+        fs.load_dir("/data/App/2.1/features").unwrap();
+        let path = Path::new("/data/App/2.1/features/ENG-1 Login/comments.md");
+        assert_eq!(fs.lookup(path).unwrap().size, 0);
+        // `echo First >> comments.md` writes without reading the empty file
+        fs.write(path, b"First\n").unwrap();
+        let posted = fs.aha.posted.borrow();
+        assert_eq!(posted.len(), 1);
+        assert!(posted[0].contains("First"));
+    }
+
+    #[test]
+    fn files_can_only_be_made_for_new_features_and_attachments() {
+        let mut fs = fs();
+        fs.load_dir("/data").unwrap();
+        fs.load_dir("/data/App").unwrap();
+        fs.load_dir("/data/App/2.1/features").unwrap();
+        let features = "/data/App/2.1/features";
+        assert_eq!(fs.mknod(Path::new(&format!("{}/Signup", features))), Ok(()));
+        let attachment = format!("{}/ENG-1 Login/{}/shot.png", features, ATTACHMENTS_DIR);
+        assert_eq!(fs.mknod(Path::new(&attachment)), Ok(()));
+        let swap = format!("{}/.Signup.swp", features);
+        assert_eq!(fs.mknod(Path::new(&swap)), Err(libc::EACCES));
+        let requirement = format!("{}/ENG-1 Login/{}/new", features, REQUIREMENTS_DIR);
+        assert_eq!(fs.mknod(Path::new(&requirement)), Err(libc::EACCES));
+        assert_eq!(fs.mknod(Path::new("/data/App/notes")), Err(libc::EACCES));
+    }
+
+    #[test]
+    fn unknown_directories_are_missing() {
+        let mut fs = fs();
+        assert_eq!(fs.load_dir("/data/Nope").err(), Some(ENOENT));
+    }
+}
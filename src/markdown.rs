@@ -0,0 +1,597 @@
+// Feature descriptions as Markdown: a YAML front matter block with what a
+// terminal user wants to see at a glance, then the description body.
+//
+// aha stores descriptions as HTML from its rich text editor, which only
+// produces a handful of tags. Both directions below cover those and drop
+// anything else, so an edit made in a text editor comes back as HTML aha
+// renders the same way.
+use super::models::{Comment, Feature};
+use regex::Regex;
+use serde_json::Value;
+use std::sync::OnceLock;
+
+const FRONT_MATTER: &str = "---";
+
+// A pattern compiled the first time it is used and kept from then on.
+macro_rules! regex {
+    ($pattern:expr) => {{
+        static REGEX: OnceLock<Regex> = OnceLock::new();
+        REGEX.get_or_init(|| Regex::new($pattern).unwrap())
+    }};
+}
+
+// Characters to_html reads as formatting wherever they are, and so escapes
+// with a backslash in plain text.
+const INLINE_MARKERS: &str = "\\*_`[~";
+
+pub fn feature(feature: &Feature) -> String {
+    let extra = |key: &str| feature.extra.get(key).cloned().unwrap_or(Value::Null);
+    let release = feature
+        .extra
+        .get("release")
+        .and_then(|release| release.get("name"))
+        .cloned()
+        .unwrap_or(Value::Null);
+    let fields = vec![
+        ("reference", json(&feature.reference_num)),
+        (
+            "status",
+            json(&feature.workflow_status.as_ref().map(|s| &s.name)),
+        ),
+        (
+            "assignee",
+            json(&feature.assigned_to_user.as_ref().map(|u| &u.email)),
+        ),
+        ("tags", json(&feature.tags)),
+        ("score", extra("score")),
+        ("release", release),
+        ("url", json(&feature.url)),
+    ];
+
+    let mut text = format!("{}\n", FRONT_MATTER);
+    for (key, value) in fields {
+        // json scalars and arrays are valid yaml flow values
+        text.push_str(&format!("{}: {}\n", key, value));
+    }
+    text.push_str(&format!("{}\n\n", FRONT_MATTER));
+    text.push_str(&to_markdown(feature.description.body()));
+    text.push('\n');
+    text
+}
+
+// A comment thread, each comment under a heading with its author and when
+// it was written (in UTC, like aha sends it).
+pub fn comments(comments: &[Comment]) -> String {
+    let mut text = String::new();
+    for comment in comments {
+        let author = match &comment.user {
+            Some(user) => match &user.name {
+                Some(name) => format!("{} <{}>", name, user.email),
+                None => user.email.clone(),
+            },
+            None => "unknown".to_string(),
+        };
+        let at = comment.created_at.as_ref().and_then(|at| at.get(..16));
+        let at = at.map_or(String::new(), |at| at.replace('T', " ") + " UTC");
+        text.push_str(&format!("### {}, {}\n\n", author, at));
+        let body = comment.body.as_ref().map_or("", String::as_str);
+        text.push_str(to_markdown(body).trim_end());
+        text.push_str("\n\n");
+    }
+    text
+}
+
+fn json<T: serde::Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+// The description HTML for an edited description.md. The front matter is
+// generated, so it is dropped rather than sent back.
+pub fn description_html(text: &str) -> String {
+    to_html(strip_front_matter(text))
+}
+
+fn strip_front_matter(text: &str) -> &str {
+    let mut lines = text.split_inclusive('\n');
+    let mut offset = match lines.next() {
+        Some(first) if first.trim_end() == FRONT_MATTER => first.len(),
+        _ => return text,
+    };
+    for line in lines {
+        offset += line.len();
+        if line.trim_end() == FRONT_MATTER {
+            return &text[offset.min(text.len())..];
+        }
+    }
+    text
+}
+
+enum List {
+    Unordered,
+    Ordered(usize),
+}
+
+// HTML to Markdown. Blockquotes are rendered into their own buffer and
+// prefixed with "> " once closed.
+pub fn to_markdown(html: &str) -> String {
+    let tag = regex!(r"(?s)<(/?)([a-zA-Z0-9]+)([^>]*)>");
+    let mut out = vec![String::new()];
+    let mut lists: Vec<List> = vec![];
+    let mut links: Vec<String> = vec![];
+    let mut pre = false;
+    let mut code = false;
+    let mut last = 0;
+
+    for caps in tag.captures_iter(html) {
+        let whole = caps.get(0).unwrap();
+        let text = decode(&html[last..whole.start()]);
+        last = whole.end();
+        let buffer = out.last_mut().unwrap();
+        if pre {
+            buffer.push_str(&text);
+        } else {
+            push_text(buffer, &text, !code);
+        }
+
+        let closing = &caps[1] == "/";
+        let name = caps[2].to_lowercase();
+        let attrs = &caps[3];
+        match (name.as_str(), closing) {
+            ("p", _) | ("div", _) | ("table", _) | ("tr", true) => block(buffer),
+            ("br", false) => buffer.push_str("  \n"),
+            ("hr", false) => {
+                block(buffer);
+                buffer.push_str("---\n\n");
+            }
+            (h, false) if is_heading(h) => {
+                block(buffer);
+                let level = h[1..].parse().unwrap_or(1);
+                buffer.push_str(&format!("{} ", "#".repeat(level)));
+            }
+            (h, true) if is_heading(h) => block(buffer),
+            ("strong", _) | ("b", _) => buffer.push_str("**"),
+            ("em", _) | ("i", _) => buffer.push('*'),
+            ("s", _) | ("del", _) | ("strike", _) => buffer.push_str("~~"),
+            ("code", closing) if !pre => {
+                buffer.push('`');
+                code = !closing;
+            }
+            ("pre", false) => {
+                block(buffer);
+                buffer.push_str("```\n");
+                pre = true;
+            }
+            ("pre", true) => {
+                if !buffer.ends_with('\n') {
+                    buffer.push('\n');
+                }
+                buffer.push_str("```\n\n");
+                pre = false;
+            }
+            ("a", false) => {
+                buffer.push('[');
+                links.push(attr(attrs, "href").unwrap_or_default());
+            }
+            ("a", true) => {
+                let href = links.pop().unwrap_or_default();
+                buffer.push_str(&format!("]({})", href));
+            }
+            ("img", false) => {
+                let alt = attr(attrs, "alt").unwrap_or_default();
+                let src = attr(attrs, "src").unwrap_or_default();
+                buffer.push_str(&format!("![{}]({})", alt, src));
+            }
+            ("ul", false) | ("ol", false) => {
+                if lists.is_empty() {
+                    block(buffer);
+                }
+                lists.push(if name == "ul" {
+                    List::Unordered
+                } else {
+                    List::Ordered(0)
+                });
+            }
+            ("ul", true) | ("ol", true) => {
+                lists.pop();
+                if lists.is_empty() {
+                    block(buffer);
+                }
+            }
+            ("li", false) => {
+                line(buffer);
+                let indent = "   ".repeat(lists.len().saturating_sub(1));
+                let marker = match lists.last_mut() {
+                    Some(List::Ordered(n)) => {
+                        *n += 1;
+                        format!("{}. ", n)
+                    }
+                    _ => "- ".to_string(),
+                };
+                buffer.push_str(&indent);
+                buffer.push_str(&marker);
+            }
+            ("td", true) | ("th", true) => buffer.push(' '),
+            ("blockquote", false) => {
+                block(buffer);
+                out.push(String::new());
+            }
+            ("blockquote", true) if out.len() > 1 => {
+                let quoted = out.pop().unwrap();
+                let buffer = out.last_mut().unwrap();
+                for quoted_line in quoted.trim().lines() {
+                    buffer.push_str(format!("> {}", quoted_line).trim_end());
+                    buffer.push('\n');
+                }
+                buffer.push('\n');
+            }
+            _ => (),
+        }
+    }
+    let rest = decode(&html[last..]);
+    push_text(out.last_mut().unwrap(), &rest, !code);
+
+    let text = out.concat();
+    regex!(r"\n{3,}")
+        .replace_all(text.trim(), "\n\n")
+        .to_string()
+}
+
+fn is_heading(name: &str) -> bool {
+    name.len() == 2 && name.starts_with('h') && name[1..].parse::<u8>().is_ok()
+}
+
+// Text between tags, with the source formatting's line breaks and runs of
+// spaces collapsed the way a browser would, and escaped unless it is code.
+fn push_text(buffer: &mut String, text: &str, escaped: bool) {
+    let mut collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.is_empty() {
+        if !text.is_empty() && !buffer.ends_with(char::is_whitespace) && !buffer.is_empty() {
+            buffer.push(' ');
+        }
+        return;
+    }
+    if text.starts_with(char::is_whitespace) && !buffer.ends_with(char::is_whitespace) {
+        collapsed.insert(0, ' ');
+    }
+    if text.ends_with(char::is_whitespace) {
+        collapsed.push(' ');
+    }
+    if buffer.ends_with('\n') {
+        collapsed = collapsed.trim_start().to_string();
+    }
+    if escaped {
+        collapsed = escape(&collapsed, buffer.is_empty() || buffer.ends_with('\n'));
+    }
+    buffer.push_str(&collapsed);
+}
+
+// Backslashes what to_html would take for formatting: the inline markers
+// anywhere, and at the start of a line what starts a heading, list,
+// blockquote or rule.
+fn escape(text: &str, line_start: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut rest = text;
+    if line_start {
+        let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        match text[digits..].chars().next() {
+            Some('.') | Some(')') if digits > 0 => {
+                escaped.push_str(&text[..digits]);
+                escaped.push('\\');
+                rest = &text[digits..];
+            }
+            Some('#') | Some('-') | Some('+') | Some('>') if digits == 0 => escaped.push('\\'),
+            _ => (),
+        }
+    }
+    for c in rest.chars() {
+        if INLINE_MARKERS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn line(buffer: &mut String) {
+    let trimmed = buffer.trim_end_matches(' ').len();
+    buffer.truncate(trimmed);
+    if !buffer.is_empty() && !buffer.ends_with('\n') {
+        buffer.push('\n');
+    }
+}
+
+fn block(buffer: &mut String) {
+    line(buffer);
+    if !buffer.is_empty() && !buffer.ends_with("\n\n") {
+        buffer.push('\n');
+    }
+}
+
+// The value of the `name` attribute in a tag's attributes.
+fn attr(attrs: &str, name: &str) -> Option<String> {
+    let caps = regex!(r#"([a-zA-Z0-9-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .captures_iter(attrs)
+        .find(|caps| caps[1].eq_ignore_ascii_case(name))?;
+    let value = caps.get(2).or_else(|| caps.get(3))?;
+    Some(decode(value.as_str()))
+}
+
+fn decode(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+fn encode(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Markdown to HTML, for the subset to_markdown writes: paragraphs, headings,
+// fenced code, lists (nested by indentation), blockquotes, rules, and
+// inline emphasis, code, links and images.
+pub fn to_html(markdown: &str) -> String {
+    let heading = regex!(r"^(#{1,6})\s+(.*?)\s*#*$");
+    let item = regex!(r"^(\s*)(?:[-*+]|(\d+)[.)])\s+(.*)$");
+    let rule = regex!(r"^\s*([-*_])(\s*[-*_]){2,}\s*$");
+
+    let lines = markdown.lines().collect::<Vec<_>>();
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = vec![];
+    // open lists as (indent, closing tag)
+    let mut lists: Vec<(usize, &str)> = vec![];
+    let mut i = 0;
+
+    while i < lines.len() {
+        let text = lines[i];
+        let trimmed = text.trim();
+
+        let starts_block = trimmed.is_empty()
+            || trimmed.starts_with("```")
+            || trimmed.starts_with('>')
+            || heading.is_match(trimmed)
+            || rule.is_match(text)
+            || item.is_match(text);
+        if starts_block && !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>", inline(&paragraph.join("\n"))));
+            paragraph.clear();
+        }
+        if !item.is_match(text) && !trimmed.is_empty() && !is_continuation(text, &lists) {
+            close_lists(&mut html, &mut lists, 0);
+        }
+
+        if trimmed.starts_with("```") {
+            let mut code = vec![];
+            i += 1;
+            while i < lines.len() && !lines[i].trim().starts_with("```") {
+                code.push(lines[i]);
+                i += 1;
+            }
+            html.push_str(&format!(
+                "<pre><code>{}</code></pre>",
+                encode(&code.join("\n"))
+            ));
+        } else if trimmed.starts_with('>') {
+            let mut quoted = vec![];
+            while i < lines.len() && lines[i].trim().starts_with('>') {
+                let line = lines[i].trim().trim_start_matches('>');
+                quoted.push(line.strip_prefix(' ').unwrap_or(line));
+                i += 1;
+            }
+            html.push_str(&format!(
+                "<blockquote>{}</blockquote>",
+                to_html(&quoted.join("\n"))
+            ));
+            continue;
+        } else if let Some(caps) = heading.captures(trimmed) {
+            let level = caps[1].len();
+            html.push_str(&format!("<h{0}>{1}</h{0}>", level, inline(&caps[2])));
+        } else if rule.is_match(text) {
+            html.push_str("<hr>");
+        } else if let Some(caps) = item.captures(text) {
+            let indent = caps[1].len();
+            let (open, close) = if caps.get(2).is_some() {
+                ("<ol>", "</ol>")
+            } else {
+                ("<ul>", "</ul>")
+            };
+            close_lists(&mut html, &mut lists, indent + 1);
+            if let Some((open_indent, open_close)) = lists.last() {
+                // a list of the other kind at the same depth starts a new list
+                if *open_indent == indent && *open_close != close {
+                    close_lists(&mut html, &mut lists, indent);
+                }
+            }
+            match lists.last() {
+                Some((open_indent, _)) if *open_indent == indent => html.push_str("</li>"),
+                _ => {
+                    html.push_str(open);
+                    lists.push((indent, close));
+                }
+            }
+            html.push_str(&format!("<li>{}", inline(&caps[3])));
+        } else if !trimmed.is_empty() && is_continuation(text, &lists) {
+            html.push_str(&format!(" {}", inline(trimmed)));
+        } else if !trimmed.is_empty() {
+            paragraph.push(text);
+        }
+        i += 1;
+    }
+    if !paragraph.is_empty() {
+        html.push_str(&format!("<p>{}</p>", inline(&paragraph.join("\n"))));
+    }
+    close_lists(&mut html, &mut lists, 0);
+    html
+}
+
+// An indented line inside a list item, rather than a new paragraph.
+fn is_continuation(text: &str, lists: &[(usize, &str)]) -> bool {
+    !lists.is_empty() && text.starts_with(char::is_whitespace)
+}
+
+// Closes the lists nested deeper than `indent`.
+fn close_lists(html: &mut String, lists: &mut Vec<(usize, &str)>, indent: usize) {
+    while let Some((open_indent, close)) = lists.last() {
+        if *open_indent < indent {
+            break;
+        }
+        html.push_str("</li>");
+        html.push_str(close);
+        lists.pop();
+    }
+}
+
+// Code spans are kept as they are and everything between them is
+// formatted. A backslash escape stands in as a placeholder character while
+// the patterns run, so what it escapes comes out as plain text.
+fn inline(text: &str) -> String {
+    let mut html = String::new();
+    let mut part = String::new();
+    let mut code = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' => {
+                html.push_str(&inline_part(&part, code));
+                part.clear();
+                code = !code;
+            }
+            '\\' if !code && chars.peek().is_some_and(char::is_ascii_punctuation) => {
+                let escaped = chars.next().unwrap();
+                part.push(char::from_u32(ESCAPED + escaped as u32).unwrap());
+            }
+            _ => part.push(c),
+        }
+    }
+    html.push_str(&inline_part(&part, code));
+    html
+}
+
+// Where the placeholders for escaped ASCII punctuation start, in the
+// private use area.
+const ESCAPED: u32 = 0xF700;
+
+fn inline_part(part: &str, code: bool) -> String {
+    if code {
+        return format!("<code>{}</code>", encode(part));
+    }
+    let image = regex!(r"!\[([^\]]*)\]\(([^)\s]*)\)");
+    let link = regex!(r"\[([^\]]*)\]\(([^)\s]*)\)");
+    let strong = regex!(r"\*\*(.+?)\*\*|__(.+?)__");
+    let em = regex!(r"\*(.+?)\*|\b_(.+?)_\b");
+    let strike = regex!(r"~~(.+?)~~");
+    let hard_break = regex!(r" {2,}\n");
+
+    let part = encode(part);
+    let part = image.replace_all(&part, r#"<img src="$2" alt="$1">"#);
+    let part = link.replace_all(&part, r#"<a href="$2">$1</a>"#);
+    let part = strong.replace_all(&part, "<strong>$1$2</strong>");
+    let part = em.replace_all(&part, "<em>$1$2</em>");
+    let part = strike.replace_all(&part, "<s>$1</s>");
+    let part = hard_break.replace_all(&part, "<br>");
+    part.chars()
+        .map(|c| match (c as u32).checked_sub(ESCAPED) {
+            Some(escaped) if escaped < 0x80 => encode(&(escaped as u8 as char).to_string()),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // aha's HTML, as it comes back after a trip through description.md.
+    fn round_trip(html: &str) -> String {
+        to_html(&to_markdown(html))
+    }
+
+    #[test]
+    fn lists_round_trip() {
+        let html = "<ul><li>one</li><li>two<ol><li>first</li><li>second</li></ol></li></ul>";
+        assert_eq!(to_markdown(html), "- one\n- two\n   1. first\n   2. second");
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn code_round_trips() {
+        let html = "<p>run <code>make</code> first</p><pre><code>fn main() {\n    x &lt; y\n}</code></pre>";
+        assert_eq!(
+            to_markdown(html),
+            "run `make` first\n\n```\nfn main() {\n    x < y\n}\n```"
+        );
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn links_round_trip() {
+        let html = r#"<p>see <a href="https://example.com/a?b=1&amp;c=2">the docs</a></p>"#;
+        assert_eq!(
+            to_markdown(html),
+            "see [the docs](https://example.com/a?b=1&c=2)"
+        );
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn entities_round_trip() {
+        let html = "<p>a &amp; b &lt;c&gt; &quot;d&quot;</p>";
+        assert_eq!(to_markdown(html), "a & b <c> \"d\"");
+        assert_eq!(round_trip(html), "<p>a &amp; b &lt;c&gt; \"d\"</p>");
+    }
+
+    #[test]
+    fn plain_text_is_escaped() {
+        let html = "<p>a * b * c</p>";
+        assert_eq!(to_markdown(html), r"a \* b \* c");
+        assert_eq!(round_trip(html), html);
+        let html = "<p># of users</p>";
+        assert_eq!(to_markdown(html), r"\# of users");
+        assert_eq!(round_trip(html), html);
+    }
+
+    #[test]
+    fn markdown_characters_round_trip() {
+        for html in &[
+            "<p>snake_case_name, a ` tick, [brackets] and ~~no strike~~</p>",
+            "<p>- not a list</p>",
+            "<p>1. not a list either</p>",
+            "<p>&gt; not a quote</p>",
+            "<p>--- not a rule</p>",
+            r"<p>a \ backslash</p>",
+            "<p>run <code>a*b_c</code></p>",
+        ] {
+            assert_eq!(round_trip(html), *html);
+        }
+    }
+
+    #[test]
+    fn attributes_are_matched_by_whole_name() {
+        let html = r#"<a data-href="wrong" href='right'>x</a>"#;
+        assert_eq!(to_markdown(html), "[x](right)");
+    }
+
+    #[test]
+    fn front_matter_is_stripped() {
+        assert_eq!(strip_front_matter("---\nname: x\n---\nbody\n"), "body\n");
+        assert_eq!(strip_front_matter("---\r\nname: x\r\n---\r\nbody"), "body");
+        assert_eq!(strip_front_matter("---\n---\n"), "");
+    }
+
+    #[test]
+    fn text_without_front_matter_is_kept() {
+        assert_eq!(strip_front_matter(""), "");
+        assert_eq!(strip_front_matter("body\n---\nmore"), "body\n---\nmore");
+        assert_eq!(strip_front_matter(" ---\nbody"), " ---\nbody");
+    }
+
+    #[test]
+    fn unclosed_front_matter_is_kept() {
+        assert_eq!(strip_front_matter("---\nname: x\n"), "---\nname: x\n");
+        assert_eq!(strip_front_matter("---"), "---");
+    }
+}
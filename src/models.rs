@@ -0,0 +1,201 @@
+// Records returned by the aha api. List endpoints are called with a `fields`
+// filter, so anything beyond the id and name is optional. Fields we don't
+// model are kept in `extra` so a record serializes back to what aha sent.
+use serde_json::{Map, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Product {
+    pub id: String,
+    pub name: String,
+    pub reference_prefix: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Release {
+    pub id: String,
+    pub name: String,
+    pub reference_num: Option<String>,
+    pub start_date: Option<String>,
+    pub release_date: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Feature {
+    pub id: String,
+    pub name: String,
+    pub reference_num: Option<String>,
+    #[serde(default)]
+    pub description: Description,
+    pub workflow_status: Option<WorkflowStatus>,
+    pub assigned_to_user: Option<User>,
+    #[serde(default)]
+    pub custom_fields: Vec<CustomField>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Epic {
+    pub id: String,
+    pub name: String,
+    pub reference_num: Option<String>,
+    #[serde(default)]
+    pub description: Description,
+    pub workflow_status: Option<WorkflowStatus>,
+    pub assigned_to_user: Option<User>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Requirement {
+    pub id: String,
+    pub name: String,
+    pub reference_num: Option<String>,
+    #[serde(default)]
+    pub description: Description,
+    pub workflow_status: Option<WorkflowStatus>,
+    pub assigned_to_user: Option<User>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Comment {
+    pub id: String,
+    pub body: Option<String>,
+    pub user: Option<User>,
+    pub url: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Description {
+    pub id: Option<String>,
+    pub body: Option<String>,
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub file_name: String,
+    pub file_size: Option<u64>,
+    pub content_type: Option<String>,
+    pub download_url: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Description {
+    // aha sends a null body for an empty description
+    pub fn body(&self) -> &str {
+        self.body.as_deref().unwrap_or("")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WorkflowStatus {
+    pub id: Option<String>,
+    pub name: String,
+    pub position: Option<i64>,
+    #[serde(default)]
+    pub complete: bool,
+    pub color: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: Option<String>,
+    pub name: Option<String>,
+    pub email: String,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CustomField {
+    pub key: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub value: Value,
+    #[serde(rename = "type")]
+    pub field_type: Option<String>,
+}
+
+// What the on-disk store keys records by, and the filesystem dates them by.
+pub trait Record {
+    fn id(&self) -> &str;
+    fn created_at(&self) -> Option<&str>;
+    fn updated_at(&self) -> Option<&str>;
+}
+
+macro_rules! record {
+    ($($model:ty),*) => {
+        $(impl Record for $model {
+            fn id(&self) -> &str {
+                &self.id
+            }
+
+            fn created_at(&self) -> Option<&str> {
+                self.created_at.as_deref()
+            }
+
+            fn updated_at(&self) -> Option<&str> {
+                self.updated_at.as_deref()
+            }
+        })*
+    };
+}
+
+record!(
+    Product,
+    Release,
+    Feature,
+    Epic,
+    Requirement,
+    Comment,
+    Attachment
+);
+
+// Users assigned to a record sometimes come without an id; the account's user
+// list always has one.
+impl Record for User {
+    fn id(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.email)
+    }
+
+    fn created_at(&self) -> Option<&str> {
+        self.created_at.as_deref()
+    }
+
+    fn updated_at(&self) -> Option<&str> {
+        self.updated_at.as_deref()
+    }
+}
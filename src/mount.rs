@@ -0,0 +1,1288 @@
+// FUSE glue for a path based filesystem.
+//
+// This started out as netfuse's NetFuse (inode store, data cache, write on
+// release) and has outgrown it: the caller picks the mount options,
+// SIGINT/SIGTERM unmount the filesystem cleanly, and the hooks below cover
+// what netfuse has no room for.
+use fuse::{
+    FileAttr, FileType, Filesystem, ReplyAttr, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry,
+    ReplyOpen, ReplyWrite, ReplyXattr, Request,
+};
+use libc::{c_int, EBADF, EINVAL, EIO, ENODATA, ENOENT, ENOSYS, ENOTSUP, ERANGE};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use time::Timespec;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(1);
+const ROOT_INO: u64 = 1;
+
+// An errno, e.g. ENOENT.
+pub type LibcError = c_int;
+
+// What a filesystem says about a file or directory.
+pub struct Metadata {
+    pub size: u64,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    pub crtime: Timespec,
+    pub kind: FileType,
+    pub perm: u16,
+}
+
+// One entry of a directory listing.
+pub struct DirEntry {
+    pub filename: OsString,
+    pub metadata: Metadata,
+}
+
+impl DirEntry {
+    pub fn new<S: AsRef<OsStr>>(filename: S, metadata: Metadata) -> DirEntry {
+        DirEntry {
+            filename: filename.as_ref().to_owned(),
+            metadata,
+        }
+    }
+}
+
+// The filesystem itself, addressed by path. The driver numbers the inodes,
+// buffers file bodies and caches attributes and listings, so a read is asked
+// for the whole body once per open and a write is handed the whole body once
+// the last handle is closed (or on fsync).
+pub trait NetworkFilesystem {
+    fn init(&mut self) -> Result<(), LibcError> {
+        Ok(())
+    }
+
+    fn lookup(&mut self, _path: &Path) -> Result<Metadata, LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn read(&mut self, _path: &Path, _buffer: &mut Vec<u8>) -> Result<usize, LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn write(&mut self, _path: &Path, _data: &[u8]) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+
+    // Page `page` (from 0) of the listing of `path` being read through
+    // `handle`, or None once the listing has no more. A large directory is
+    // fetched as the reader gets to it instead of all before the first entry.
+    fn readdir(
+        &mut self,
+        _path: &Path,
+        _handle: u64,
+        _page: usize,
+    ) -> Result<Option<Vec<DirEntry>>, LibcError> {
+        Err(ENOSYS)
+    }
+
+    // The handle a directory was read through is closed.
+    fn releasedir(&mut self, _handle: u64) {}
+
+    // A file is about to be made at `path`; refusing it here fails the
+    // create itself. What it should become is written on close as usual.
+    fn mknod(&mut self, _path: &Path) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn mkdir(&mut self, _path: &Path) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn rmdir(&mut self, _path: &Path) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn unlink(&mut self, _path: &Path) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+}
+
+// What the driver asks of a filesystem on top of NetworkFilesystem.
+pub trait CachePolicy {
+    // How long the kernel and the driver may trust an entry, its attributes
+    // and, for a directory, its listing before asking the filesystem again.
+    fn ttl(&self, _path: &Path) -> Duration {
+        DEFAULT_TTL
+    }
+
+    // Called when a file or directory is touched, to drop whatever the
+    // filesystem has cached for it.
+    fn invalidate(&mut self, _ino: u64, _path: &Path) {}
+}
+
+// Lets a filesystem number its own inodes, so they stay the same across
+// listings and remounts. Paths it has no number for get the next free one.
+pub trait StableInodes {
+    fn ino(&mut self, _path: &Path) -> Option<u64> {
+        None
+    }
+}
+
+// Extended attributes of the file or directory at a path.
+pub trait ExtendedAttributes {
+    // The names of the attributes `path` has a value for.
+    fn xattrs(&mut self, _path: &Path) -> Result<Vec<String>, LibcError> {
+        Ok(vec![])
+    }
+
+    fn xattr(&mut self, _path: &Path, _name: &str) -> Result<Vec<u8>, LibcError> {
+        Err(ENODATA)
+    }
+
+    fn set_xattr(&mut self, _path: &Path, _name: &str, _value: &[u8]) -> Result<(), LibcError> {
+        Err(ENOTSUP)
+    }
+}
+
+// Symbolic links, for entries that stand in for another one elsewhere in the
+// mount.
+pub trait Links {
+    fn readlink(&mut self, _path: &Path) -> Result<PathBuf, LibcError> {
+        Err(EINVAL)
+    }
+}
+
+// mv within the mount, for filesystems that give moving an entry a meaning.
+pub trait Renames {
+    fn rename(&mut self, _from: &Path, _to: &Path) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+}
+
+// Files too big to buffer whole between open and release, like downloads.
+// Reads ask for one range at a time, writes are handed over as they come and
+// `close` is called once the last handle is released.
+pub trait Streams {
+    fn is_streamed(&mut self, _path: &Path) -> bool {
+        false
+    }
+
+    fn read_at(&mut self, _path: &Path, _offset: u64, _size: u32) -> Result<Vec<u8>, LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn write_at(&mut self, _path: &Path, _offset: u64, _data: &[u8]) -> Result<(), LibcError> {
+        Err(ENOSYS)
+    }
+
+    fn close(&mut self, _path: &Path) -> Result<(), LibcError> {
+        Ok(())
+    }
+}
+
+// Everything the driver needs from a filesystem.
+pub trait Backend:
+    NetworkFilesystem + CachePolicy + StableInodes + ExtendedAttributes + Links + Renames + Streams
+{
+}
+
+impl<T> Backend for T where
+    T: NetworkFilesystem
+        + CachePolicy
+        + StableInodes
+        + ExtendedAttributes
+        + Links
+        + Renames
+        + Streams
+{
+}
+
+pub struct MountOptions {
+    pub path: PathBuf,
+    // Passed through to FUSE as "-o <option>", e.g. "allow_other" or "ro".
+    pub options: Vec<String>,
+    uid: u32,
+    gid: u32,
+}
+
+impl MountOptions {
+    pub fn new<P: AsRef<Path>>(path: P, options: Vec<String>) -> MountOptions {
+        MountOptions {
+            path: path.as_ref().to_path_buf(),
+            options,
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        }
+    }
+
+    fn fuse_args(&self) -> Vec<OsString> {
+        let mut args = vec![OsString::from("-o"), OsString::from("fsname=ahafs")];
+        for option in &self.options {
+            args.push(OsString::from("-o"));
+            args.push(OsString::from(option));
+        }
+        args
+    }
+}
+
+// Mounts the filesystem and serves requests until it is unmounted, either by
+// `unmount` from another process or by a SIGINT/SIGTERM to this one.
+pub fn mount<NFS: Backend>(fs: NFS, options: &MountOptions) -> io::Result<()> {
+    if !options.path.exists() {
+        fs::create_dir_all(&options.path)?;
+    }
+    let driver = Driver {
+        nfs: fs,
+        inodes: InodeStore::new(0o550, options.uid, options.gid),
+        cache: HashMap::new(),
+        dirs: HashMap::new(),
+        next_fh: 1,
+    };
+
+    unmount_on_signal(options.path.clone());
+    let args = options.fuse_args();
+    let args: Vec<&OsStr> = args.iter().map(|a| a.as_os_str()).collect();
+    println!("mounting {}", options.path.display());
+    fuse::Session::new(driver, &options.path, &args)?.run()?;
+    println!("unmounted {}", options.path.display());
+    Ok(())
+}
+
+pub fn unmount<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    let status = Command::new("fusermount")
+        .arg("-u")
+        .arg(path.as_ref())
+        .status()?;
+    #[cfg(not(target_os = "linux"))]
+    let status = Command::new("umount").arg(path.as_ref()).status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "could not unmount {}",
+            path.as_ref().display()
+        )))
+    }
+}
+
+// Blocks SIGINT and SIGTERM for this thread and every thread it starts from
+// here on. `mount` does this itself, but a caller that starts threads of its
+// own before mounting has to do it first, or the signals can land on one of
+// those and kill the process with the mount still attached.
+pub fn block_signals() -> libc::sigset_t {
+    let mut signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+    }
+    signals
+}
+
+// Waits for SIGINT and SIGTERM on a dedicated thread, with them blocked
+// everywhere else, so the FUSE loop is never killed mid-request and the mount
+// point is not left behind as "Transport endpoint is not connected".
+fn unmount_on_signal(path: PathBuf) {
+    let signals = block_signals();
+    thread::spawn(move || loop {
+        let mut signal: c_int = 0;
+        if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+            continue;
+        }
+        println!("caught signal {}, unmounting {}", signal, path.display());
+        match unmount(&path) {
+            Ok(_) => break,
+            Err(err) => println!("unmount failed: {}", err),
+        }
+    });
+}
+
+#[derive(Clone)]
+struct Inode {
+    path: PathBuf,
+    attr: FileAttr,
+    // When the attributes need asking for again.
+    expires: Instant,
+    // Until when the children in the store are the whole listing.
+    listed_until: Option<Instant>,
+}
+
+impl Inode {
+    fn is_listed(&self) -> bool {
+        self.listed_until
+            .map(|until| until > Instant::now())
+            .unwrap_or(false)
+    }
+}
+
+struct InodeStore {
+    inodes: HashMap<u64, Inode>,
+    paths: HashMap<PathBuf, u64>,
+    next_ino: u64,
+    uid: u32,
+    gid: u32,
+}
+
+impl InodeStore {
+    fn new(perm: u16, uid: u32, gid: u32) -> InodeStore {
+        let mut store = InodeStore {
+            inodes: HashMap::new(),
+            paths: HashMap::new(),
+            next_ino: 1,
+            uid,
+            gid,
+        };
+        let now = time::now_utc().to_timespec();
+        store.insert_metadata(
+            "/",
+            &Metadata {
+                size: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                crtime: now,
+                kind: FileType::Directory,
+                perm,
+            },
+            DEFAULT_TTL,
+            None,
+        );
+        store
+    }
+
+    fn get(&self, ino: u64) -> Option<&Inode> {
+        self.inodes.get(&ino)
+    }
+
+    fn get_mut(&mut self, ino: u64) -> Option<&mut Inode> {
+        self.inodes.get_mut(&ino)
+    }
+
+    fn get_path(&self, path: &Path) -> Option<&Inode> {
+        self.paths.get(path).and_then(|ino| self.get(*ino))
+    }
+
+    fn insert_metadata<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        metadata: &Metadata,
+        ttl: Duration,
+        ino: Option<u64>,
+    ) -> &Inode {
+        let path = path.as_ref().to_path_buf();
+        let current = self.paths.get(&path).cloned();
+        let ino = match ino.or(current) {
+            Some(ino) => ino,
+            None => {
+                let ino = self.next_ino;
+                self.next_ino += 1;
+                ino
+            }
+        };
+        // The path used to be something else, e.g. a new feature with the
+        // name of a deleted one.
+        if let Some(old) = current {
+            if old != ino {
+                self.inodes.remove(&old);
+            }
+        }
+        // The inode used to be at another path, e.g. renamed in aha.
+        let moved = self
+            .inodes
+            .get(&ino)
+            .filter(|inode| inode.path != path)
+            .map(|inode| inode.path.clone());
+        if let Some(old_path) = moved {
+            self.paths.remove(&old_path);
+        }
+        let attr = FileAttr {
+            ino,
+            size: metadata.size,
+            blocks: 0,
+            atime: metadata.atime,
+            mtime: metadata.mtime,
+            ctime: metadata.ctime,
+            crtime: metadata.crtime,
+            kind: metadata.kind,
+            perm: metadata.perm,
+            nlink: 0,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: 0,
+        };
+        let listed_until = self
+            .inodes
+            .get(&ino)
+            .filter(|inode| inode.path == path)
+            .and_then(|inode| inode.listed_until);
+        self.paths.insert(path.clone(), ino);
+        self.inodes.insert(
+            ino,
+            Inode {
+                path,
+                attr,
+                expires: Instant::now() + ttl,
+                listed_until,
+            },
+        );
+        &self.inodes[&ino]
+    }
+
+    fn child(&self, ino: u64, name: &OsStr) -> Option<&Inode> {
+        let path = self.get(ino)?.path.join(name);
+        self.paths.get(&path).and_then(|ino| self.get(*ino))
+    }
+
+    fn children(&self, ino: u64) -> Vec<&Inode> {
+        match self.get(ino) {
+            Some(dir) => self
+                .inodes
+                .values()
+                .filter(|inode| inode.attr.ino != ino && inode.path.parent() == Some(&dir.path))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn parent(&self, ino: u64) -> Option<&Inode> {
+        let parent = self.get(ino)?.path.parent()?;
+        self.paths.get(parent).and_then(|ino| self.get(*ino))
+    }
+
+    // Moves an inode to where it was renamed to, replacing what was there.
+    // Anything below it is looked up again under the new path.
+    fn rename(&mut self, ino: u64, to: PathBuf) {
+        if let Some(replaced) = self.paths.get(&to).cloned() {
+            if replaced != ino {
+                self.remove(replaced);
+            }
+        }
+        let from = match self.inodes.get(&ino) {
+            Some(inode) => inode.path.clone(),
+            None => return,
+        };
+        let below: Vec<u64> = self
+            .inodes
+            .values()
+            .filter(|inode| inode.attr.ino != ino && inode.path.starts_with(&from))
+            .map(|inode| inode.attr.ino)
+            .collect();
+        for ino in below {
+            self.remove(ino);
+        }
+        self.paths.remove(&from);
+        self.paths.insert(to.clone(), ino);
+        if let Some(inode) = self.inodes.get_mut(&ino) {
+            inode.path = to;
+            inode.expires = Instant::now();
+            inode.listed_until = None;
+        }
+    }
+
+    // Removes the inode and, for a directory, everything below it.
+    fn remove(&mut self, ino: u64) {
+        let path = match self.inodes.remove(&ino) {
+            Some(inode) => inode.path,
+            None => return,
+        };
+        self.paths.remove(&path);
+        let below: Vec<u64> = self
+            .inodes
+            .values()
+            .filter(|inode| inode.path.starts_with(&path))
+            .map(|inode| inode.attr.ino)
+            .collect();
+        for ino in below {
+            if let Some(inode) = self.inodes.remove(&ino) {
+                self.paths.remove(&inode.path);
+            }
+        }
+    }
+}
+
+// File contents buffered between open and release.
+struct CacheEntry {
+    data: Vec<u8>,
+    // Filled from the backend or by a write.
+    warm: bool,
+    // Matches the backend; false means it still needs to be written.
+    sync: bool,
+    // Made by mknod and not written yet, so the filesystem has never heard
+    // of it.
+    created: bool,
+    handles: u32,
+}
+
+impl CacheEntry {
+    fn new() -> CacheEntry {
+        CacheEntry {
+            data: Vec::new(),
+            warm: false,
+            sync: false,
+            created: false,
+            handles: 0,
+        }
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        self.sync = false;
+        self.warm = true;
+        let end = offset as usize + data.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[(offset as usize)..end].copy_from_slice(data);
+    }
+}
+
+// A directory open for reading. The entries are kept for as long as the
+// handle is, so every offset handed to the kernel keeps pointing at the same
+// entry; an entry's offset is its index plus one.
+struct DirHandle {
+    ino: u64,
+    path: PathBuf,
+    entries: Vec<(u64, FileType, OsString)>,
+    // The page of the listing to ask for next, None once it is complete.
+    next_page: Option<usize>,
+    seen: HashSet<u64>,
+}
+
+struct Driver<NFS: NetworkFilesystem> {
+    nfs: NFS,
+    inodes: InodeStore,
+    // Keyed by inode number.
+    cache: HashMap<u64, CacheEntry>,
+    // Keyed by file handle.
+    dirs: HashMap<u64, DirHandle>,
+    next_fh: u64,
+}
+
+impl<NFS: Backend> Driver<NFS> {
+    // true if data was written, false if nothing needed writing
+    fn flush_cache_if_needed(&mut self, ino: u64) -> Result<bool, LibcError> {
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return Err(ENOENT),
+        };
+        let entry = match self.cache.get_mut(&ino) {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        if !entry.warm || entry.sync {
+            return Ok(false);
+        }
+        self.nfs.write(&path, &entry.data)?;
+        entry.sync = true;
+        entry.created = false;
+        Ok(true)
+    }
+
+    fn read_to_cache_if_needed(&mut self, ino: u64) -> Result<bool, LibcError> {
+        if self.cache.get(&ino).map(|e| e.warm).unwrap_or(false) {
+            return Ok(false);
+        }
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return Err(ENOENT),
+        };
+        let mut buffer = Vec::new();
+        self.nfs.read(&path, &mut buffer)?;
+        let entry = self.cache.entry(ino).or_insert_with(CacheEntry::new);
+        entry.data = buffer;
+        entry.warm = true;
+        entry.sync = true;
+        Ok(true)
+    }
+
+    // Truncation (O_TRUNC, `truncate`) changes the buffered body too, and is
+    // written straight away when no handle is going to release it.
+    fn truncate(&mut self, ino: u64, new_size: u64) -> Result<(), LibcError> {
+        if new_size > 0 {
+            self.read_to_cache_if_needed(ino)?;
+        }
+        let entry = self.cache.entry(ino).or_insert_with(CacheEntry::new);
+        entry.data.resize(new_size as usize, 0);
+        entry.warm = true;
+        entry.sync = false;
+        if entry.handles == 0 {
+            self.flush_cache_if_needed(ino)?;
+            self.cache.remove(&ino);
+        }
+        Ok(())
+    }
+
+    fn streamed_path(&mut self, ino: u64) -> Option<PathBuf> {
+        let path = self.inodes.get(ino)?.path.clone();
+        if self.nfs.is_streamed(&path) {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    // The attributes for `path`, asking the filesystem again once the inode
+    // has outlived its ttl. Files with buffered data are left as they are.
+    fn attr(&mut self, path: &Path) -> Result<(FileAttr, Duration), LibcError> {
+        let ttl = self.nfs.ttl(path);
+        if let Some(inode) = self.inodes.get_path(path) {
+            let ino = inode.attr.ino;
+            if ino == ROOT_INO || inode.expires > Instant::now() || self.cache.contains_key(&ino) {
+                return Ok((inode.attr, ttl));
+            }
+        }
+        match self.nfs.lookup(path) {
+            Ok(metadata) => Ok(self.remember(path, &metadata)),
+            Err(err) => {
+                if err == ENOENT {
+                    if let Some(ino) = self.inodes.get_path(path).map(|i| i.attr.ino) {
+                        self.inodes.remove(ino);
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    // Stores what the filesystem said about `path`, numbered by the
+    // filesystem when it can. An inode with buffered data keeps its number
+    // until it is released.
+    fn remember(&mut self, path: &Path, metadata: &Metadata) -> (FileAttr, Duration) {
+        let ttl = self.nfs.ttl(path);
+        let current = self.inodes.get_path(path).map(|inode| inode.attr.ino);
+        let ino = match current {
+            Some(ino) if self.cache.contains_key(&ino) => Some(ino),
+            _ => self.nfs.ino(path),
+        };
+        let attr = self.inodes.insert_metadata(path, metadata, ttl, ino).attr;
+        (attr, ttl)
+    }
+
+    // The listing read through `fh` is complete: forget whatever it no
+    // longer has, unless it is still being written, and trust it for a ttl.
+    fn listed(&mut self, fh: u64) {
+        let dir = match self.dirs.get_mut(&fh) {
+            Some(dir) => dir,
+            None => return,
+        };
+        dir.next_page = None;
+        let (ino, seen) = (dir.ino, std::mem::take(&mut dir.seen));
+        let ttl = self.nfs.ttl(&dir.path);
+        let gone: Vec<u64> = self
+            .inodes
+            .children(ino)
+            .into_iter()
+            .map(|child| child.attr.ino)
+            .filter(|child| !seen.contains(child) && !self.cache.contains_key(child))
+            .collect();
+        for child in gone {
+            self.inodes.remove(child);
+        }
+        if let Some(dir) = self.inodes.get_mut(ino) {
+            dir.listed_until = Some(Instant::now() + ttl);
+        }
+    }
+
+    fn new_metadata(kind: FileType, perm: u16) -> Metadata {
+        let now = time::now_utc().to_timespec();
+        Metadata {
+            size: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind,
+            perm,
+        }
+    }
+}
+
+impl<NFS: Backend> Filesystem for Driver<NFS> {
+    fn init(&mut self, _req: &Request) -> Result<(), c_int> {
+        self.nfs.init()
+    }
+
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let child_path = match self.inodes.get(parent) {
+            Some(inode) => inode.path.join(name),
+            None => return reply.error(ENOENT),
+        };
+        match self.attr(&child_path) {
+            Ok((attr, ttl)) => reply.entry(&timespec(ttl), &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return reply.error(ENOENT),
+        };
+        match self.attr(&path) {
+            Ok((attr, ttl)) => reply.attr(&timespec(ttl), &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        reply: ReplyData,
+    ) {
+        let offset = offset as u64;
+        if let Some(path) = self.streamed_path(ino) {
+            return match self.nfs.read_at(&path, offset, size) {
+                Ok(data) => reply.data(&data),
+                Err(err) => reply.error(err),
+            };
+        }
+        if let Err(err) = self.read_to_cache_if_needed(ino) {
+            return reply.error(err);
+        }
+        let buffer = &self.cache[&ino].data;
+        let start = (offset as usize).min(buffer.len());
+        let end = (offset as usize + size as usize).min(buffer.len());
+        reply.data(&buffer[start..end]);
+    }
+
+    fn opendir(&mut self, _req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+        let (path, listed) = match self.inodes.get(ino) {
+            Some(inode) => (inode.path.clone(), inode.is_listed()),
+            None => return reply.error(ENOENT),
+        };
+        let parent_ino = self.inodes.parent(ino).map(|p| p.attr.ino).unwrap_or(ino);
+        let mut entries = vec![
+            (ino, FileType::Directory, OsString::from(".")),
+            (parent_ino, FileType::Directory, OsString::from("..")),
+        ];
+        let next_page = if listed {
+            for child in self.inodes.children(ino) {
+                let name = child.path.file_name().expect("missing filename");
+                entries.push((child.attr.ino, child.attr.kind, name.to_owned()));
+            }
+            None
+        } else {
+            Some(0)
+        };
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.dirs.insert(
+            fh,
+            DirHandle {
+                ino,
+                path,
+                entries,
+                next_page,
+                seen: HashSet::new(),
+            },
+        );
+        reply.opened(fh, 0);
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        _ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let skip = offset as usize;
+        // Fetch pages until there is something past `offset` to hand on.
+        loop {
+            let (path, page) = match self.dirs.get(&fh) {
+                Some(dir) if skip < dir.entries.len() => break,
+                Some(dir) => match dir.next_page {
+                    Some(page) => (dir.path.clone(), page),
+                    None => break,
+                },
+                None => return reply.error(EBADF),
+            };
+            match self.nfs.readdir(&path, fh, page) {
+                Ok(Some(listed)) => {
+                    let mut found = Vec::with_capacity(listed.len());
+                    for entry in listed {
+                        let child_path = path.join(&entry.filename);
+                        let (attr, _) = self.remember(&child_path, &entry.metadata);
+                        found.push((attr.ino, attr.kind, entry.filename));
+                    }
+                    let dir = self.dirs.get_mut(&fh).expect("missing dir handle");
+                    dir.seen.extend(found.iter().map(|(ino, _, _)| *ino));
+                    dir.entries.extend(found);
+                    dir.next_page = Some(page + 1);
+                }
+                Ok(None) => self.listed(fh),
+                Err(err) => return reply.error(err),
+            }
+        }
+        for (i, (ino, kind, name)) in self.dirs[&fh].entries.iter().enumerate().skip(skip) {
+            // true once the kernel's buffer is full; it asks again from there.
+            if reply.add(*ino, i as i64 + 1, *kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn releasedir(&mut self, _req: &Request, _ino: u64, fh: u64, _flags: u32, reply: ReplyEmpty) {
+        self.dirs.remove(&fh);
+        self.nfs.releasedir(fh);
+        reply.ok();
+    }
+
+    fn mknod(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _rdev: u32,
+        reply: ReplyEntry,
+    ) {
+        let path = match self.inodes.get(parent) {
+            Some(inode) => inode.path.join(name),
+            None => return reply.error(ENOENT),
+        };
+        if let Err(err) = self.nfs.mknod(&path) {
+            return reply.error(err);
+        }
+        let meta = Self::new_metadata(FileType::RegularFile, (mode & 0o777) as u16);
+        let (attr, ttl) = self.remember(&path, &meta);
+
+        // Warm and out of sync, so even an empty file is written on close.
+        if !self.nfs.is_streamed(&path) {
+            let entry = self.cache.entry(attr.ino).or_insert_with(CacheEntry::new);
+            entry.warm = true;
+            entry.sync = false;
+            entry.created = true;
+        }
+        reply.entry(&timespec(ttl), &attr, 0);
+    }
+
+    fn mkdir(&mut self, _req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        let path = match self.inodes.get(parent) {
+            Some(inode) => inode.path.join(name),
+            None => return reply.error(ENOENT),
+        };
+        match self.nfs.mkdir(&path) {
+            Ok(_) => {
+                let meta = Self::new_metadata(FileType::Directory, (mode & 0o777) as u16);
+                let (attr, ttl) = self.remember(&path, &meta);
+                reply.entry(&timespec(ttl), &attr, 0);
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn open(&mut self, _req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        let entry = self.cache.entry(ino).or_insert_with(CacheEntry::new);
+        entry.handles += 1;
+        reply.opened(0, flags);
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let handles = match self.cache.get_mut(&ino) {
+            Some(entry) => {
+                entry.handles = entry.handles.saturating_sub(1);
+                entry.handles
+            }
+            None => return reply.ok(),
+        };
+        if handles > 0 {
+            return reply.ok();
+        }
+        if let Some(path) = self.streamed_path(ino) {
+            self.cache.remove(&ino);
+            return match self.nfs.close(&path) {
+                Ok(_) => reply.ok(),
+                Err(err) => {
+                    println!("release close error - {}", err);
+                    reply.error(err)
+                }
+            };
+        }
+        let result = self.flush_cache_if_needed(ino);
+        if let Some(entry) = self.cache.get(&ino) {
+            if entry.created && result.is_err() {
+                // the filesystem refused it, so there is nothing to list
+                self.inodes.remove(ino);
+                self.cache.remove(&ino);
+            } else if entry.sync || !entry.warm {
+                self.cache.remove(&ino);
+            }
+        }
+        match result {
+            Ok(_) => reply.ok(),
+            Err(err) => {
+                println!("release flush error - {}", err);
+                reply.error(err)
+            }
+        }
+    }
+
+    // Sent on every close(2), which waits for the answer; release is not,
+    // and what it answers is dropped. So the last handle's body is written
+    // here, and a write the filesystem refuses fails the close.
+    fn flush(&mut self, _req: &Request, ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        let last = self.cache.get(&ino).map(|entry| entry.handles <= 1);
+        if last != Some(true) || self.streamed_path(ino).is_some() {
+            return reply.ok();
+        }
+        match self.flush_cache_if_needed(ino) {
+            Ok(_) => reply.ok(),
+            Err(err) => {
+                println!("flush error - {}", err);
+                reply.error(err)
+            }
+        }
+    }
+
+    fn fsync(&mut self, _req: &Request, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        match self.flush_cache_if_needed(ino) {
+            Ok(_) => reply.ok(),
+            Err(err) => {
+                println!("fsync error - {}", err);
+                reply.error(EIO);
+            }
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        let offset = offset as u64;
+        if let Some(path) = self.streamed_path(ino) {
+            if let Err(err) = self.nfs.write_at(&path, offset, data) {
+                return reply.error(err);
+            }
+            if let Some(inode) = self.inodes.get_mut(ino) {
+                inode.attr.size = inode.attr.size.max(offset + data.len() as u64);
+            }
+            return reply.written(data.len() as u32);
+        }
+        let size = match self.inodes.get(ino) {
+            Some(inode) => inode.attr.size,
+            None => return reply.error(ENOENT),
+        };
+        // Skip fetching the old body when this write replaces all of it.
+        let is_replace = offset == 0 && size <= data.len() as u64;
+        if !is_replace {
+            if let Err(err) = self.read_to_cache_if_needed(ino) {
+                return reply.error(err);
+            }
+        }
+        let entry = self.cache.entry(ino).or_insert_with(CacheEntry::new);
+        if is_replace {
+            entry.data.clear();
+        }
+        entry.write(offset, data);
+        let new_size = entry.data.len() as u64;
+        if let Some(inode) = self.inodes.get_mut(ino) {
+            inode.attr.size = new_size;
+        }
+        reply.written(data.len() as u32);
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        _fh: Option<u64>,
+        _crtime: Option<Timespec>,
+        _chgtime: Option<Timespec>,
+        _bkuptime: Option<Timespec>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // Streamed files have no buffered body to truncate.
+        let streamed = self.streamed_path(ino).is_some();
+        if let Some(new_size) = size.filter(|_| !streamed) {
+            if let Err(err) = self.truncate(ino, new_size) {
+                return reply.error(err);
+            }
+        }
+        // A bare `touch` asks for a fresh copy on the next stat, ls or read.
+        if size.is_none() && (atime.is_some() || mtime.is_some()) {
+            if let Some(inode) = self.inodes.get_mut(ino) {
+                inode.expires = Instant::now();
+                inode.listed_until = None;
+                let path = inode.path.clone();
+                self.nfs.invalidate(ino, &path);
+            }
+        }
+        match self.inodes.get_mut(ino) {
+            Some(inode) => {
+                if let Some(new_size) = size {
+                    inode.attr.size = new_size;
+                }
+                if let Some(new_uid) = uid {
+                    inode.attr.uid = new_uid;
+                }
+                if let Some(new_gid) = gid {
+                    inode.attr.gid = new_gid;
+                }
+                reply.attr(&timespec(self.nfs.ttl(&inode.path)), &inode.attr);
+            }
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return reply.error(ENOENT),
+        };
+        match self.nfs.readlink(&path) {
+            Ok(target) => reply.data(target.as_os_str().as_bytes()),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEmpty,
+    ) {
+        let (from, to) = match (self.inodes.get(parent), self.inodes.get(newparent)) {
+            (Some(from_dir), Some(to_dir)) => (from_dir.path.join(name), to_dir.path.join(newname)),
+            _ => return reply.error(ENOENT),
+        };
+        if let Err(err) = self.nfs.rename(&from, &to) {
+            return reply.error(err);
+        }
+        if let Some(ino) = self.inodes.get_path(&from).map(|inode| inode.attr.ino) {
+            self.inodes.rename(ino, to);
+        }
+        for dir in &[parent, newparent] {
+            if let Some(inode) = self.inodes.get_mut(*dir) {
+                inode.listed_until = None;
+            }
+        }
+        reply.ok()
+    }
+
+    fn getxattr(&mut self, _req: &Request, ino: u64, name: &OsStr, size: u32, reply: ReplyXattr) {
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return reply.error(ENOENT),
+        };
+        match self.nfs.xattr(&path, &name.to_string_lossy()) {
+            Ok(value) => reply_xattr(reply, size, &value),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &Request, ino: u64, size: u32, reply: ReplyXattr) {
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return reply.error(ENOENT),
+        };
+        match self.nfs.xattrs(&path) {
+            Ok(names) => {
+                let mut list = Vec::new();
+                for name in names {
+                    list.extend_from_slice(name.as_bytes());
+                    list.push(0);
+                }
+                reply_xattr(reply, size, &list)
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        name: &OsStr,
+        value: &[u8],
+        _flags: u32,
+        _position: u32,
+        reply: ReplyEmpty,
+    ) {
+        let path = match self.inodes.get(ino) {
+            Some(inode) => inode.path.clone(),
+            None => return reply.error(ENOENT),
+        };
+        match self.nfs.set_xattr(&path, &name.to_string_lossy(), value) {
+            Ok(_) => {
+                // The change can show in the entry's size and dates too.
+                if let Some(inode) = self.inodes.get_mut(ino) {
+                    inode.expires = Instant::now();
+                }
+                reply.ok()
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.remove(parent, name, true, reply)
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.remove(parent, name, false, reply)
+    }
+}
+
+impl<NFS: Backend> Driver<NFS> {
+    fn remove(&mut self, parent: u64, name: &OsStr, dir: bool, reply: ReplyEmpty) {
+        let ino = self.inodes.child(parent, name).map(|inode| inode.attr.ino);
+        let path = match self.inodes.get(parent) {
+            Some(inode) => inode.path.join(name),
+            None => return reply.error(ENOENT),
+        };
+        let created = ino
+            .and_then(|ino| self.cache.get(&ino))
+            .map(|entry| entry.created)
+            .unwrap_or(false);
+        let result = if created {
+            // only the driver knows about it so far
+            Ok(())
+        } else if dir {
+            self.nfs.rmdir(&path)
+        } else {
+            self.nfs.unlink(&path)
+        };
+        match result {
+            Ok(_) => {
+                if let Some(ino) = ino {
+                    self.inodes.remove(ino);
+                    self.cache.remove(&ino);
+                }
+                reply.ok()
+            }
+            Err(err) => reply.error(err),
+        }
+    }
+}
+
+// A size of zero asks how big the value is, so the caller can allocate for it.
+fn reply_xattr(reply: ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32)
+    } else if (size as usize) < value.len() {
+        reply.error(ERANGE)
+    } else {
+        reply.data(value)
+    }
+}
+
+fn timespec(ttl: Duration) -> Timespec {
+    Timespec::new(ttl.as_secs() as i64, ttl.subsec_nanos() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Serves one file body and keeps every body written back.
+    struct OneFile {
+        body: Vec<u8>,
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl NetworkFilesystem for OneFile {
+        fn read(&mut self, _path: &Path, buffer: &mut Vec<u8>) -> Result<usize, LibcError> {
+            buffer.extend_from_slice(&self.body);
+            Ok(buffer.len())
+        }
+
+        fn write(&mut self, _path: &Path, data: &[u8]) -> Result<(), LibcError> {
+            self.writes.push(data.to_vec());
+            Ok(())
+        }
+    }
+
+    impl CachePolicy for OneFile {}
+    impl StableInodes for OneFile {}
+    impl ExtendedAttributes for OneFile {}
+    impl Links for OneFile {}
+    impl Renames for OneFile {}
+    impl Streams for OneFile {}
+
+    // A driver that has looked up /file, and the file's inode.
+    fn driver(body: &str) -> (Driver<OneFile>, u64) {
+        let mut driver = Driver {
+            nfs: OneFile {
+                body: body.as_bytes().to_vec(),
+                writes: vec![],
+            },
+            inodes: InodeStore::new(0o550, 0, 0),
+            cache: HashMap::new(),
+            dirs: HashMap::new(),
+            next_fh: 1,
+        };
+        let mut metadata = Driver::<OneFile>::new_metadata(FileType::RegularFile, 0o640);
+        metadata.size = body.len() as u64;
+        let ino = driver
+            .inodes
+            .insert_metadata("/file", &metadata, DEFAULT_TTL, None)
+            .attr
+            .ino;
+        (driver, ino)
+    }
+
+    #[test]
+    fn truncate_to_zero_writes_an_empty_body() {
+        let (mut driver, ino) = driver("hello world");
+        driver.truncate(ino, 0).unwrap();
+        assert_eq!(driver.nfs.writes, vec![Vec::<u8>::new()]);
+        assert!(!driver.cache.contains_key(&ino));
+    }
+
+    #[test]
+    fn truncate_keeps_the_start_of_the_body() {
+        let (mut driver, ino) = driver("hello world");
+        driver.truncate(ino, 5).unwrap();
+        assert_eq!(driver.nfs.writes, vec![b"hello".to_vec()]);
+    }
+
+    #[test]
+    fn truncate_of_an_open_file_waits_for_release() {
+        let (mut driver, ino) = driver("hello world");
+        driver
+            .cache
+            .entry(ino)
+            .or_insert_with(CacheEntry::new)
+            .handles = 1;
+        driver.truncate(ino, 0).unwrap();
+        assert!(driver.nfs.writes.is_empty());
+        assert!(driver.cache[&ino].data.is_empty());
+        assert_eq!(driver.flush_cache_if_needed(ino), Ok(true));
+        assert_eq!(driver.nfs.writes, vec![Vec::<u8>::new()]);
+    }
+}
@@ -0,0 +1,238 @@
+// File names for aha records. A name has to be a legal path component and
+// unique in its directory, while aha allows any title, duplicates included.
+use std::collections::HashSet;
+use std::str::FromStr;
+
+// What a record's file name is made of. Set with --naming or `naming` in
+// ~/.aha_workflow.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Naming {
+    // "ENG-123 Feature title"
+    #[default]
+    RefAndName,
+    // "ENG-123"
+    RefOnly,
+    // "Feature title"
+    NameOnly,
+}
+
+impl FromStr for Naming {
+    type Err = String;
+
+    fn from_str(naming: &str) -> Result<Naming, String> {
+        match naming {
+            "ref-and-name" => Ok(Naming::RefAndName),
+            "ref-only" => Ok(Naming::RefOnly),
+            "name-only" => Ok(Naming::NameOnly),
+            _ => Err(format!(
+                "unknown naming {:?}, use ref-and-name, ref-only or name-only",
+                naming
+            )),
+        }
+    }
+}
+
+impl Naming {
+    // Records without a reference number, like products, go by name.
+    fn base(self, reference: Option<&str>, name: &str) -> String {
+        match (self, reference) {
+            (Naming::RefOnly, Some(reference)) => reference.to_string(),
+            (Naming::RefAndName, Some(reference)) => format!("{} {}", reference, name),
+            _ => name.to_string(),
+        }
+    }
+}
+
+// "/" and NUL can't be in a file name and a leading "." would hide the file,
+// so those are percent escaped, along with "%" itself.
+pub fn escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '/' => escaped.push_str("%2F"),
+            '\0' => escaped.push_str("%00"),
+            '.' if i == 0 => escaped.push_str("%2E"),
+            c => escaped.push(c),
+        }
+    }
+    if escaped.trim().is_empty() {
+        escaped = "untitled".to_string();
+    }
+    escaped
+}
+
+// The title a file name made with mkdir stands for; the reverse of escape,
+// so anything escape leaves alone is taken as typed.
+pub fn unescape(name: &str) -> String {
+    let mut title = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(c) = rest.chars().next() {
+        let escaped = match rest.get(..3) {
+            Some("%25") => Some('%'),
+            Some("%2F") => Some('/'),
+            Some("%00") => Some('\0'),
+            Some("%2E") if title.is_empty() => Some('.'),
+            _ => None,
+        };
+        match escaped {
+            Some(c) => {
+                title.push(c);
+                rest = &rest[3..];
+            }
+            None => {
+                title.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    title
+}
+
+// Files editors and file managers make next to the ones they work on: hidden
+// files (no listed name starts with "."), vim's swap files and the numbered
+// file it probes a directory with, and "~" backups. None of them should turn
+// into a feature.
+pub fn is_scratch(name: &str) -> bool {
+    let vim_probe = match name.parse::<u64>() {
+        Ok(n) => n >= 4913 && (n - 4913) % 123 == 0,
+        Err(_) => false,
+    };
+    name.starts_with('.')
+        || name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".swo")
+        || name.ends_with(".swx")
+        || vim_probe
+}
+
+// Hands out the names in one directory listing. Later records with a name
+// already taken get " (2)", " (3)" and so on, in the order aha lists them.
+pub struct Names {
+    naming: Naming,
+    taken: HashSet<String>,
+}
+
+impl Names {
+    // `reserved` are the fixed entries of the directory, like meta.json.
+    pub fn new(naming: Naming, reserved: &[&str]) -> Names {
+        Names {
+            naming,
+            taken: reserved.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    pub fn name(&mut self, reference: Option<&str>, name: &str) -> String {
+        self.name_with(reference, name, "")
+    }
+
+    // For records listed as two entries, the name and the name plus
+    // `suffix`, both of which have to be free.
+    pub fn name_with(&mut self, reference: Option<&str>, name: &str, suffix: &str) -> String {
+        let base = escape(&self.naming.base(reference, name));
+        self.claim_with(base, suffix)
+    }
+
+    // Makes a name that is already a file name, like a feature's name under
+    // features/, unique here.
+    pub fn claim(&mut self, name: String) -> String {
+        self.claim_with(name, "")
+    }
+
+    fn claim_with(&mut self, base: String, suffix: &str) -> String {
+        let mut unique = base.clone();
+        let mut n = 1;
+        while self.taken.contains(&unique) || self.taken.contains(&(unique.clone() + suffix)) {
+            n += 1;
+            unique = format!("{} ({})", base, n);
+        }
+        self.taken.insert(unique.clone() + suffix);
+        self.taken.insert(unique.clone());
+        unique
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_round_trip() {
+        for title in &[
+            "a/b", "100%", "50%2F", "a%2Eb", ".hidden", "..", "a.b.", "x\0y",
+        ] {
+            assert_eq!(&unescape(&escape(title)), title);
+        }
+    }
+
+    #[test]
+    fn only_a_leading_dot_is_escaped() {
+        assert_eq!(escape(".hidden"), "%2Ehidden");
+        assert_eq!(escape("v1.2"), "v1.2");
+        assert_eq!(unescape("%2Ehidden"), ".hidden");
+        assert_eq!(unescape("a%2Eb"), "a%2Eb");
+    }
+
+    #[test]
+    fn slashes_and_percents_are_escaped() {
+        assert_eq!(escape("a/b"), "a%2Fb");
+        assert_eq!(escape("100%"), "100%25");
+        assert_eq!(escape("50%2F"), "50%252F");
+        assert_eq!(unescape("50%252F"), "50%2F");
+    }
+
+    #[test]
+    fn blank_titles_are_untitled() {
+        assert_eq!(escape(""), "untitled");
+        assert_eq!(escape("  "), "untitled");
+    }
+
+    #[test]
+    fn duplicates_are_numbered_in_order() {
+        let mut names = Names::new(Naming::NameOnly, &[]);
+        assert_eq!(names.name(None, "Login"), "Login");
+        assert_eq!(names.name(None, "Login"), "Login (2)");
+        assert_eq!(names.name(None, "Login"), "Login (3)");
+    }
+
+    #[test]
+    fn reserved_names_are_never_handed_out() {
+        let mut names = Names::new(Naming::NameOnly, &["meta.json"]);
+        assert_eq!(names.name(None, "meta.json"), "meta.json (2)");
+    }
+
+    #[test]
+    fn both_names_of_a_pair_are_taken() {
+        let mut names = Names::new(Naming::NameOnly, &[]);
+        assert_eq!(names.name_with(None, "Epic", ".html"), "Epic");
+        assert_eq!(names.name(None, "Epic.html"), "Epic.html (2)");
+        assert_eq!(names.name(None, "Epic"), "Epic (2)");
+    }
+
+    #[test]
+    fn references_follow_the_naming() {
+        let reference = Some("ENG-1");
+        assert_eq!(
+            Names::new(Naming::RefAndName, &[]).name(reference, "A"),
+            "ENG-1 A"
+        );
+        assert_eq!(
+            Names::new(Naming::RefOnly, &[]).name(reference, "A"),
+            "ENG-1"
+        );
+        assert_eq!(Names::new(Naming::NameOnly, &[]).name(reference, "A"), "A");
+        assert_eq!(Names::new(Naming::RefOnly, &[]).name(None, "A"), "A");
+        assert_eq!(Naming::default(), Naming::RefAndName);
+    }
+
+    #[test]
+    fn editor_scratch_files_are_recognised() {
+        for name in &[".DS_Store", ".x.swp", "x.swo", "notes~", "4913", "5036"] {
+            assert!(is_scratch(name), "{}", name);
+        }
+        for name in &["Login", "2024", "v1.2", "%2Ehidden"] {
+            assert!(!is_scratch(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+// What an entry in the mount is in aha. Inode numbers are derived from this
+// rather than handed out in listing order, so a feature keeps its inode
+// across listings, renames and remounts.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Object {
+    Product(String),
+    Release(String),
+    // the epics/ and features/ folders, by release id
+    Epics(String),
+    Features(String),
+    // an epic's directory of features
+    Epic(String),
+    // an epic's "<name>.html" description
+    EpicFile(String),
+    Feature(String),
+    // a feature listed under an epic; a second inode for the same feature,
+    // as the driver gives every inode a single path
+    EpicFeature { epic: String, feature: String },
+    // the description.md, requirements/ and attachments/ in a feature
+    // directory, by the Feature or EpicFeature they belong to
+    Description(Box<Object>),
+    Requirements(Box<Object>),
+    Requirement { feature: Box<Object>, id: String },
+    Attachments(Box<Object>),
+    Attachment { feature: Box<Object>, id: String },
+    // the meta.json of a release, epic or feature directory
+    Meta(Box<Object>),
+    // the comments.md of an epic or feature directory
+    Comments(Box<Object>),
+    // the by-status directory of a product or release
+    StatusView(Box<Object>),
+    // the top level /mine, /by-assignee, /search and /ref directories, and
+    // the links in /ref, by path
+    View(String),
+    // a feature's link in a view; the same in whichever status directory it
+    // is, so moving it between them keeps the inode
+    Link { view: Box<Object>, feature: String },
+}
+
+// Inodes the driver numbers itself count up from 1; derived ones all have
+// the top bit set so the two never meet.
+const DERIVED_INO: u64 = 1 << 63;
+
+impl Object {
+    pub fn key(&self) -> String {
+        match self {
+            Object::Product(id) => format!("product/{}", id),
+            Object::Release(id) => format!("release/{}", id),
+            Object::Epics(id) => format!("release/{}/epics", id),
+            Object::Features(id) => format!("release/{}/features", id),
+            Object::Epic(id) => format!("epic/{}", id),
+            Object::EpicFile(id) => format!("epic/{}/file", id),
+            Object::Feature(id) => format!("feature/{}", id),
+            Object::EpicFeature { epic, feature } => format!("epic/{}/feature/{}", epic, feature),
+            Object::Description(feature) => format!("{}/description", feature.key()),
+            Object::Requirements(feature) => format!("{}/requirements", feature.key()),
+            Object::Requirement { feature, id } => format!("{}/requirement/{}", feature.key(), id),
+            Object::Attachments(feature) => format!("{}/attachments", feature.key()),
+            Object::Attachment { feature, id } => format!("{}/attachment/{}", feature.key(), id),
+            Object::Meta(owner) => format!("{}/meta", owner.key()),
+            Object::Comments(owner) => format!("{}/comments", owner.key()),
+            Object::StatusView(owner) => format!("{}/by-status", owner.key()),
+            Object::View(name) => format!("view/{}", name),
+            Object::Link { view, feature } => format!("{}/link/{}", view.key(), feature),
+        }
+    }
+
+    // FNV-1a of the key, which only depends on the kind and the aha ids.
+    pub fn ino(&self) -> u64 {
+        let hash = self
+            .key()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+            });
+        hash | DERIVED_INO
+    }
+
+    // The key the body of this entry is cached under, shared by every path
+    // the same feature or epic shows up at.
+    pub fn body_key(&self) -> Option<String> {
+        match self {
+            Object::Feature(id)
+            | Object::EpicFeature { feature: id, .. }
+            | Object::Link { feature: id, .. } => Some(feature_key(id)),
+            Object::Release(id) => Some(release_key(id)),
+            Object::Epic(id) | Object::EpicFile(id) => Some(epic_key(id)),
+            Object::Description(owner) => owner.body_key(),
+            Object::Meta(owner) => owner.body_key().as_deref().map(meta_key),
+            Object::Comments(owner) => owner.body_key().as_deref().map(comments_key),
+            _ => None,
+        }
+    }
+}
+
+pub fn release_key(id: &str) -> String {
+    format!("release/{}", id)
+}
+
+pub fn feature_key(id: &str) -> String {
+    format!("feature/{}", id)
+}
+
+pub fn epic_key(id: &str) -> String {
+    format!("epic/{}", id)
+}
+
+// Below its owner's key, so touching a feature or epic drops its thread too.
+pub fn comments_key(owner_key: &str) -> String {
+    format!("{}/comments", owner_key)
+}
+
+// The whole record a meta.json shows, kept apart from the listing's copy.
+pub fn meta_key(owner_key: &str) -> String {
+    format!("{}/meta", owner_key)
+}
@@ -0,0 +1,606 @@
+// A copy of everything the mount fetched, kept under ~/.cache/ahafs so the
+// next mount can list and read straight away, and --offline can work with
+// no network at all.
+//
+// The first time a listing or record is asked for it is served from the
+// store, if it has it, while a second client refreshes it in the background.
+// After that every ask goes to aha, so the mount's own cache times and
+// `touch` decide how fresh things are. Writes always go to aha first.
+use super::aha::{
+    self, AhaError, Api, FeatureCreate, FeatureSearch, FeatureUpdateCreate, PageIter,
+};
+use super::models::{
+    Attachment, Comment, Epic, Feature, Product, Record, Release, Requirement, User,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::iter;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Something that can be fetched from aha, and the file it is stored in.
+#[derive(Clone, Debug)]
+pub enum Source {
+    Products,
+    Releases(String),
+    Features(String),
+    Epics(String),
+    EpicFeatures(String),
+    Requirements(String),
+    Users,
+    AssignedFeatures(String),
+    Release(String),
+    Feature(String),
+    Epic(String),
+    Requirement(String),
+    // the comments on a "feature", "epic" or "requirement"
+    Comments(String, String),
+}
+
+impl Source {
+    fn key(&self) -> String {
+        match self {
+            Source::Products => "products".to_string(),
+            Source::Releases(id) => format!("releases/{}", id),
+            Source::Features(id) => format!("features/{}", id),
+            Source::Epics(id) => format!("epics/{}", id),
+            Source::EpicFeatures(id) => format!("epic_features/{}", id),
+            Source::Requirements(id) => format!("requirements/{}", id),
+            Source::Users => "users".to_string(),
+            Source::AssignedFeatures(email) => format!("assigned_features/{}", email),
+            Source::Release(id)
+            | Source::Feature(id)
+            | Source::Epic(id)
+            | Source::Requirement(id) => record_key(id),
+            Source::Comments(base, id) => format!("comments/{}/{}", base, id),
+        }
+    }
+}
+
+// Records are keyed by their aha id alone; ids are unique across types.
+fn record_key(id: &str) -> String {
+    format!("records/{}", id)
+}
+
+#[derive(Clone)]
+pub struct Store {
+    dir: PathBuf,
+}
+
+impl Store {
+    pub fn open(dir: PathBuf) -> io::Result<Store> {
+        fs::create_dir_all(&dir)?;
+        Ok(Store { dir })
+    }
+
+    // $XDG_CACHE_HOME/ahafs, usually ~/.cache/ahafs
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::cache_dir().map(|dir| dir.join("ahafs"))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn read<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let text = fs::read_to_string(self.path(key)).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn write<T: Serialize>(&self, key: &str, value: &T) {
+        if let Err(err) = self.try_write(key, value) {
+            println!("AFS store error: {}: {}", key, err);
+        }
+    }
+
+    // Written next to the real file and renamed over it, so a crash never
+    // leaves half a listing behind.
+    fn try_write<T: Serialize>(&self, key: &str, value: &T) -> io::Result<()> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(value)?)?;
+        fs::rename(&partial, &path)
+    }
+
+    // Keeps whichever copy of a record aha updated last. Two copies of the
+    // same version are merged, so the few fields a listing asks for don't
+    // replace a record fetched whole.
+    pub fn save_record<T: Record + Serialize + DeserializeOwned>(&self, record: &T) {
+        let key = record_key(record.id());
+        let stored = match self.read::<T>(&key) {
+            Some(stored) => stored,
+            None => return self.write(&key, record),
+        };
+        if stored.updated_at() > record.updated_at() {
+            return;
+        }
+        if stored.updated_at() < record.updated_at() {
+            return self.write(&key, record);
+        }
+        match (serde_json::to_value(stored), serde_json::to_value(record)) {
+            (Ok(Value::Object(mut merged)), Ok(Value::Object(fields))) => {
+                merged.extend(fields.into_iter().filter(|(_, value)| !value.is_null()));
+                self.write(&key, &merged);
+            }
+            _ => self.write(&key, record),
+        }
+    }
+
+    pub fn load_record<T: DeserializeOwned>(&self, id: &str) -> Option<T> {
+        self.read(&record_key(id))
+    }
+
+    pub fn save_listing<T: Record + Serialize + DeserializeOwned>(
+        &self,
+        source: &Source,
+        items: &[T],
+    ) {
+        self.write(&source.key(), &items);
+        for item in items {
+            self.save_record(item);
+        }
+    }
+
+    // A stored listing, with records that changed since it was saved (by a
+    // write, or a single record fetch) swapped in.
+    pub fn load_listing<T: Record + DeserializeOwned>(&self, source: &Source) -> Option<Vec<T>> {
+        let items: Vec<T> = self.read(&source.key())?;
+        let items = items
+            .into_iter()
+            .map(|item| match self.load_record::<T>(item.id()) {
+                Some(record) if record.updated_at() > item.updated_at() => record,
+                _ => item,
+            })
+            .collect();
+        Some(items)
+    }
+}
+
+// An Api that answers from the store and keeps it up to date.
+pub struct Stored<A: Api> {
+    api: A,
+    store: Store,
+    offline: bool,
+    revalidate: Option<Sender<Source>>,
+    // Sources queued for revalidation, so a busy directory is only fetched
+    // once per round.
+    pending: Arc<Mutex<HashSet<String>>>,
+    // Sources already answered from the store once this mount.
+    served: Mutex<HashSet<String>>,
+    // Listings whose first page last came from the store, so there is no
+    // later page to ask aha for.
+    from_store: Mutex<HashSet<String>>,
+    // Listings being read from aha a page at a time: the pages read so far
+    // and their records, stored once the last page is in.
+    partial: Mutex<HashMap<String, (usize, Vec<Value>)>>,
+}
+
+impl<A: Api> Stored<A> {
+    // `background` is a second client, used by the revalidating thread.
+    pub fn new<B: Api + Send + 'static>(
+        api: A,
+        store: Store,
+        offline: bool,
+        background: B,
+    ) -> Stored<A> {
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let revalidate = if offline {
+            None
+        } else {
+            let (sender, receiver) = channel();
+            let (thread_store, thread_pending) = (store.clone(), pending.clone());
+            thread::spawn(move || revalidate(background, thread_store, receiver, thread_pending));
+            Some(sender)
+        };
+        Stored {
+            api,
+            store,
+            offline,
+            revalidate,
+            pending,
+            served: Mutex::new(HashSet::new()),
+            from_store: Mutex::new(HashSet::new()),
+            partial: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Whether the stored copy of `key` can answer: always offline, otherwise
+    // only the first time it is asked for.
+    fn serve_stored(&self, key: String) -> bool {
+        self.offline || self.served.lock().unwrap().insert(key)
+    }
+
+    fn queue(&self, source: Source) {
+        if let Some(sender) = &self.revalidate {
+            if self.pending.lock().unwrap().insert(source.key()) {
+                let _ = sender.send(source);
+            }
+        }
+    }
+
+    // A listing is read a page at a time, possibly across several calls that
+    // each skip to the page they want (see Listing), so a large listing is
+    // only fetched as far as it is read.
+    fn listing<'s, T, F>(&'s self, source: Source, fetch: F) -> PageIter<'s, T>
+    where
+        T: Record + Serialize + DeserializeOwned + 's,
+        F: FnOnce(&'s A) -> PageIter<'s, T> + 's,
+    {
+        Box::new(Listing {
+            stored: self,
+            source,
+            fetch: Some(fetch),
+            pages: None,
+            next_page: 0,
+        })
+    }
+
+    // Where page `index` of a listing comes from, and how many pages of that
+    // to skip to get to it. The first page comes from the store as above,
+    // and the later ones from wherever the first one did.
+    fn pages<'s, T, F>(
+        &'s self,
+        source: &Source,
+        index: usize,
+        fetch: F,
+    ) -> (PageIter<'s, T>, usize)
+    where
+        T: Record + Serialize + DeserializeOwned + 's,
+        F: FnOnce(&'s A) -> PageIter<'s, T>,
+    {
+        let key = source.key();
+        if index == 0 && self.serve_stored(key.clone()) {
+            if let Some(items) = self.store.load_listing::<T>(source) {
+                // the records in it have been seen too
+                let records = items.iter().map(|item| record_key(item.id()));
+                self.served.lock().unwrap().extend(records);
+                self.from_store.lock().unwrap().insert(key);
+                self.queue(source.clone());
+                return (Box::new(iter::once(Ok(items))), 0);
+            }
+        }
+        if index == 0 {
+            self.from_store.lock().unwrap().remove(&key);
+        } else if self.from_store.lock().unwrap().contains(&key) {
+            return (Box::new(iter::empty()), 0);
+        }
+        if self.offline {
+            return (Box::new(iter::once(Err(AhaError::Offline(key)))), 0);
+        }
+        (fetch(&self.api), index)
+    }
+
+    // Keeps page `index` of a listing read from aha, and stores the listing
+    // once every page up to the end came through in order. A listing that
+    // failed or was skipped through part way is not stored.
+    fn keep<T>(&self, source: &Source, index: usize, page: &Option<Result<Vec<T>, AhaError>>)
+    where
+        T: Record + Serialize + DeserializeOwned,
+    {
+        let key = source.key();
+        if self.from_store.lock().unwrap().contains(&key) {
+            return;
+        }
+        let mut partial = self.partial.lock().unwrap();
+        match page {
+            Some(Ok(items)) => {
+                if index == 0 {
+                    partial.insert(key.clone(), (0, vec![]));
+                }
+                match partial.get_mut(&key) {
+                    Some((pages, kept)) if *pages == index => {
+                        *pages += 1;
+                        kept.extend(
+                            items
+                                .iter()
+                                .filter_map(|item| serde_json::to_value(item).ok()),
+                        );
+                    }
+                    _ => {
+                        partial.remove(&key);
+                    }
+                }
+            }
+            Some(Err(_)) => {
+                partial.remove(&key);
+            }
+            None => {
+                let items: Vec<T> = match partial.remove(&key) {
+                    Some((pages, kept)) if pages == index => kept
+                        .into_iter()
+                        .filter_map(|value| serde_json::from_value(value).ok())
+                        .collect(),
+                    Some(_) => return,
+                    // nothing at all
+                    None if index == 0 => vec![],
+                    None => return,
+                };
+                self.store.save_listing(source, &items);
+            }
+        }
+    }
+
+    fn record<T, F>(&self, source: Source, id: &str, fetch: F) -> Result<T, AhaError>
+    where
+        T: Record + Serialize + DeserializeOwned,
+        F: FnOnce(&A) -> Result<T, AhaError>,
+    {
+        if self.serve_stored(record_key(id)) {
+            if let Some(record) = self.store.load_record(id) {
+                self.queue(source);
+                return Ok(record);
+            }
+        }
+        if self.offline {
+            return Err(AhaError::Offline(source.key()));
+        }
+        let record = fetch(&self.api)?;
+        self.store.save_record(&record);
+        Ok(record)
+    }
+}
+
+impl<A: Api> Api for Stored<A> {
+    fn products(&self) -> PageIter<'_, Product> {
+        self.listing(Source::Products, |api| api.products())
+    }
+
+    fn releases(&self, product_id: &str) -> PageIter<'_, Release> {
+        let source = Source::Releases(product_id.to_string());
+        let product_id = product_id.to_string();
+        self.listing(source, move |api| api.releases(&product_id))
+    }
+
+    fn features(&self, release_id: &str) -> PageIter<'_, Feature> {
+        let source = Source::Features(release_id.to_string());
+        let release_id = release_id.to_string();
+        self.listing(source, move |api| api.features(&release_id))
+    }
+
+    fn epics(&self, release_id: &str) -> PageIter<'_, Epic> {
+        let source = Source::Epics(release_id.to_string());
+        let release_id = release_id.to_string();
+        self.listing(source, move |api| api.epics(&release_id))
+    }
+
+    fn epic_features(&self, epic_id: &str) -> PageIter<'_, Feature> {
+        let source = Source::EpicFeatures(epic_id.to_string());
+        let epic_id = epic_id.to_string();
+        self.listing(source, move |api| api.epic_features(&epic_id))
+    }
+
+    fn requirements(&self, feature_id: &str) -> PageIter<'_, Requirement> {
+        let source = Source::Requirements(feature_id.to_string());
+        let feature_id = feature_id.to_string();
+        self.listing(source, move |api| api.requirements(&feature_id))
+    }
+
+    fn users(&self) -> PageIter<'_, User> {
+        self.listing(Source::Users, |api| api.users())
+    }
+
+    fn assigned_features(&self, email: &str) -> PageIter<'_, Feature> {
+        let source = Source::AssignedFeatures(email.to_string());
+        let email = email.to_string();
+        self.listing(source, move |api| api.assigned_features(&email))
+    }
+
+    // Searches are one-offs, so they always go to aha and aren't stored as a
+    // listing; the features they find are.
+    fn search_features(&self, search: &FeatureSearch) -> PageIter<'_, Feature> {
+        if self.offline {
+            let query = format!("search {:?}", search);
+            return Box::new(iter::once(Err(AhaError::Offline(query))));
+        }
+        Box::new(Recorded {
+            pages: self.api.search_features(search),
+            store: &self.store,
+        })
+    }
+
+    fn feature(&self, feature_id: &str) -> Result<Feature, AhaError> {
+        let source = Source::Feature(feature_id.to_string());
+        self.record(source, feature_id, |api| api.feature(feature_id))
+    }
+
+    fn release(&self, release_id: &str) -> Result<Release, AhaError> {
+        let source = Source::Release(release_id.to_string());
+        self.record(source, release_id, |api| api.release(release_id))
+    }
+
+    fn epic(&self, epic_id: &str) -> Result<Epic, AhaError> {
+        let source = Source::Epic(epic_id.to_string());
+        self.record(source, epic_id, |api| api.epic(epic_id))
+    }
+
+    fn requirement(&self, requirement_id: &str) -> Result<Requirement, AhaError> {
+        let source = Source::Requirement(requirement_id.to_string());
+        self.record(source, requirement_id, |api| {
+            api.requirement(requirement_id)
+        })
+    }
+
+    fn update_feature(
+        &self,
+        feature_id: &str,
+        update: &FeatureUpdateCreate,
+    ) -> Result<Feature, AhaError> {
+        if self.offline {
+            return Err(AhaError::Offline(record_key(feature_id)));
+        }
+        let feature = self.api.update_feature(feature_id, update)?;
+        self.store.save_record(&feature);
+        Ok(feature)
+    }
+
+    fn post_feature(&self, feature: &FeatureCreate) -> Result<Feature, AhaError> {
+        if self.offline {
+            return Err(AhaError::Offline(feature.name.clone()));
+        }
+        let created = self.api.post_feature(feature)?;
+        let source = Source::Features(feature.release_id.clone());
+        match self.store.load_listing::<Feature>(&source) {
+            Some(mut features) => {
+                features.push(created.clone());
+                self.store.save_listing(&source, &features);
+            }
+            None => self.store.save_record(&created),
+        }
+        Ok(created)
+    }
+
+    fn comments(&self, base: &str, id: &str) -> PageIter<'_, Comment> {
+        let source = Source::Comments(base.to_string(), id.to_string());
+        let (base, id) = (base.to_string(), id.to_string());
+        self.listing(source, move |api| api.comments(&base, &id))
+    }
+
+    // Added to the stored thread straight away, like a new feature is to its
+    // release's listing, so it shows up before the next revalidation.
+    fn post_comment(&self, base: &str, id: &str, body: &str) -> Result<Comment, AhaError> {
+        let source = Source::Comments(base.to_string(), id.to_string());
+        if self.offline {
+            return Err(AhaError::Offline(source.key()));
+        }
+        let created = self.api.post_comment(base, id, body)?;
+        if let Some(mut comments) = self.store.load_listing::<Comment>(&source) {
+            comments.push(created.clone());
+            self.store.save_listing(&source, &comments);
+        }
+        Ok(created)
+    }
+
+    // Attachments are too big to keep a copy of; they are always fetched.
+    fn download(&self, url: &str, offset: u64) -> Result<Box<dyn Read>, AhaError> {
+        if self.offline {
+            return Err(AhaError::Offline(url.to_string()));
+        }
+        self.api.download(url, offset)
+    }
+
+    fn upload_attachment(
+        &self,
+        base: &str,
+        id: &str,
+        file_name: &str,
+        file: File,
+        length: u64,
+    ) -> Result<Attachment, AhaError> {
+        if self.offline {
+            return Err(AhaError::Offline(file_name.to_string()));
+        }
+        self.api
+            .upload_attachment(base, id, file_name, file, length)
+    }
+}
+
+// The pages of one listing, fetched only once they are asked for. Skipping
+// ahead with nth skips the pages in between too, rather than fetching them.
+struct Listing<'s, A: Api, T, F> {
+    stored: &'s Stored<A>,
+    source: Source,
+    fetch: Option<F>,
+    pages: Option<PageIter<'s, T>>,
+    next_page: usize,
+}
+
+impl<'s, A, T, F> Iterator for Listing<'s, A, T, F>
+where
+    A: Api,
+    T: Record + Serialize + DeserializeOwned + 's,
+    F: FnOnce(&'s A) -> PageIter<'s, T>,
+{
+    type Item = Result<Vec<T>, AhaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let index = self.next_page + n;
+        self.next_page = index + 1;
+        let page = if let Some(pages) = &mut self.pages {
+            pages.nth(n)
+        } else if let Some(fetch) = self.fetch.take() {
+            let (mut pages, skip) = self.stored.pages(&self.source, index, fetch);
+            let page = pages.nth(skip);
+            self.pages = Some(pages);
+            page
+        } else {
+            None
+        };
+        self.stored.keep(&self.source, index, &page);
+        page
+    }
+}
+
+// Search results handed on as they are fetched, with the features in them
+// stored as records.
+struct Recorded<'s, T> {
+    pages: PageIter<'s, T>,
+    store: &'s Store,
+}
+
+impl<'s, T: Record + Serialize + DeserializeOwned> Iterator for Recorded<'s, T> {
+    type Item = Result<Vec<T>, AhaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let page = self.pages.nth(n);
+        for record in page.iter().flatten().flatten() {
+            self.store.save_record(record);
+        }
+        page
+    }
+}
+
+fn revalidate<B: Api>(
+    api: B,
+    store: Store,
+    sources: Receiver<Source>,
+    pending: Arc<Mutex<HashSet<String>>>,
+) {
+    for source in sources {
+        if let Err(err) = refresh(&api, &store, &source) {
+            println!("AFS revalidate error: {}: {}", source.key(), err);
+        }
+        pending.lock().unwrap().remove(&source.key());
+    }
+}
+
+fn refresh<B: Api>(api: &B, store: &Store, source: &Source) -> Result<(), AhaError> {
+    match source {
+        Source::Products => store.save_listing(source, &aha::all(api.products())?),
+        Source::Releases(id) => store.save_listing(source, &aha::all(api.releases(id))?),
+        Source::Features(id) => store.save_listing(source, &aha::all(api.features(id))?),
+        Source::Epics(id) => store.save_listing(source, &aha::all(api.epics(id))?),
+        Source::EpicFeatures(id) => store.save_listing(source, &aha::all(api.epic_features(id))?),
+        Source::Requirements(id) => store.save_listing(source, &aha::all(api.requirements(id))?),
+        Source::Users => store.save_listing(source, &aha::all(api.users())?),
+        Source::AssignedFeatures(email) => {
+            store.save_listing(source, &aha::all(api.assigned_features(email))?)
+        }
+        Source::Release(id) => store.save_record(&api.release(id)?),
+        Source::Feature(id) => store.save_record(&api.feature(id)?),
+        Source::Epic(id) => store.save_record(&api.epic(id)?),
+        Source::Requirement(id) => store.save_record(&api.requirement(id)?),
+        Source::Comments(base, id) => {
+            store.save_listing(source, &aha::all(api.comments(base, id))?)
+        }
+    }
+    Ok(())
+}